@@ -0,0 +1,124 @@
+//! An A-buffer for order-independent transparency.
+
+use crate::gfx::{BlendMode, ColorF32};
+
+/// Marks the end of a fragment list.
+const END: u32 = u32::MAX;
+
+/// The most fragments an A-buffer can hold, which keeps the indices of its
+/// nodes below `END`.
+pub const MAX_CAPACITY: usize = (END - 1) as usize;
+
+/// A blended fragment waiting to be composited.
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    color: ColorF32,
+    depth: f32,
+    blend: BlendMode,
+    next: u32,
+}
+
+/// Per-sample lists of blended fragments. The lists share a pool of nodes
+/// with a fixed capacity, so memory use is bounded no matter how much
+/// transparency is in a frame. The pool grows as fragments arrive, and keeps
+/// its memory from frame to frame.
+pub struct ABuffer {
+    heads: Vec<u32>,
+    nodes: Vec<Node>,
+    capacity: usize,
+    scratch: Vec<Node>,
+}
+
+impl ABuffer {
+    /// Creates an A-buffer for a number of samples that can hold up to
+    /// `capacity` fragments, or `MAX_CAPACITY` if that is less.
+    pub fn new(len: usize, capacity: usize) -> Self {
+        Self {
+            heads: vec![END; len],
+            nodes: Vec::new(),
+            capacity: capacity.min(MAX_CAPACITY),
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Drops every stored fragment without compositing it.
+    pub fn clear(&mut self) {
+        self.heads.fill(END);
+        self.nodes.clear();
+    }
+
+    /// Adds a fragment to a sample's list. Returns `false` without storing the
+    /// fragment when the pool is full.
+    pub fn insert(&mut self, sample: usize, color: ColorF32, depth: f32, blend: BlendMode) -> bool {
+        if self.nodes.len() >= self.capacity {
+            return false;
+        }
+        let next = self.heads[sample];
+        self.heads[sample] = self.nodes.len() as u32;
+        self.nodes.push(Node {
+            color,
+            depth,
+            blend,
+            next,
+        });
+        true
+    }
+
+    /// Composites every sample's fragments over the given colors from back to
+    /// front and empties the lists.
+    pub fn resolve(&mut self, colors: &mut [ColorF32]) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        for (head, color) in self.heads.iter_mut().zip(colors.iter_mut()) {
+            let mut i = *head;
+            if i == END {
+                continue;
+            }
+            self.scratch.clear();
+            while i != END {
+                let node = self.nodes[i as usize];
+                self.scratch.push(node);
+                i = node.next;
+            }
+            // Smaller depths are farther away.
+            self.scratch.sort_by(|a, b| a.depth.total_cmp(&b.depth));
+            for node in self.scratch.iter() {
+                *color = node.color.blend_over(*color, node.blend);
+            }
+            *head = END;
+        }
+
+        self.nodes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_fragments_once_full() {
+        let mut abuffer = ABuffer::new(2, 2);
+        let red = ColorF32::RED.with_alpha(0.5);
+        assert!(abuffer.insert(0, red, 0.5, BlendMode::Alpha));
+        assert!(abuffer.insert(1, red, 0.5, BlendMode::Alpha));
+        assert!(!abuffer.insert(0, red, 0.5, BlendMode::Alpha));
+
+        // Resolving empties the pool for the next frame.
+        let mut colors = [ColorF32::BLACK; 2];
+        abuffer.resolve(&mut colors);
+        assert!(abuffer.insert(0, red, 0.5, BlendMode::Alpha));
+    }
+
+    #[test]
+    fn limits_capacity_to_node_indices() {
+        assert_eq!(ABuffer::new(1, usize::MAX).capacity(), MAX_CAPACITY);
+        assert!(MAX_CAPACITY < END as usize);
+    }
+}
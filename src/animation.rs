@@ -0,0 +1,404 @@
+//! Keyframe animation of the translation, rotation and scaling of instances.
+//!
+//! Clips are read from text files of the following form, where rotations are
+//! given as x, y and z angles in degrees, and keys follow the track they
+//! belong to in increasing order of time:
+//!
+//! ```text
+//! # clip <duration in seconds> <once | loop | pingpong>
+//! clip 4 loop
+//! # track <instance> <translation | rotation | scaling> <step | linear | cubic>
+//! track 0 rotation linear
+//! # key <time in seconds> <x> <y> <z>
+//! key 0 0 0 0
+//! key 2 0 180 0
+//! key 4 0 360 0
+//! ```
+
+use crate::error::RenderError;
+use crate::math::quat::Quat;
+use crate::math::vec::{Vec3, Vec4};
+use crate::scene::Instance;
+
+/// How values are found between keys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    /// Holds each key's value until the next key.
+    Step,
+    /// Interpolates linearly, or spherically for rotations.
+    Linear,
+    /// Follows a Catmull-Rom spline through the keys.
+    Cubic,
+}
+
+/// How a clip plays once its time passes its duration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoopMode {
+    /// Stops at the end.
+    Once,
+    /// Starts over from the beginning.
+    Loop,
+    /// Plays backwards to the beginning, then forwards again.
+    PingPong,
+}
+
+/// Values that tracks can interpolate between.
+pub trait Interpolate: Copy {
+    /// Interpolates from this value to another, where `t` may lie outside
+    /// `0..=1` to extrapolate.
+    fn interpolate(self, to: Self, t: f32) -> Self;
+}
+
+impl Interpolate for Vec3 {
+    fn interpolate(self, to: Self, t: f32) -> Self {
+        self + (to - self) * t
+    }
+}
+
+impl Interpolate for Vec4 {
+    fn interpolate(self, to: Self, t: f32) -> Self {
+        self + (to - self) * t
+    }
+}
+
+impl Interpolate for Quat {
+    fn interpolate(self, to: Self, t: f32) -> Self {
+        self.slerp(to, t)
+    }
+}
+
+/// The value of a property at a time.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe<T> {
+    /// The time since the clip started in seconds.
+    pub time: f32,
+    /// The value of the property at that time.
+    pub value: T,
+}
+
+/// The keys of one animated property, ordered by time.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    /// How values are found between the keys.
+    pub interpolation: Interpolation,
+    /// The keys in increasing order of time.
+    pub keys: Vec<Keyframe<T>>,
+}
+
+/// The instance property a track animates.
+#[derive(Clone, Debug)]
+pub enum Channel {
+    /// `Instance::translation`.
+    Translation(Track<Vec4>),
+    /// `Instance::rotation`.
+    Rotation(Track<Quat>),
+    /// `Instance::scaling`.
+    Scaling(Track<Vec3>),
+}
+
+/// A set of tracks that play together, each animating a property of an
+/// instance.
+#[derive(Clone, Debug)]
+pub struct Clip {
+    /// The length of the clip in seconds.
+    pub duration: f32,
+    /// How the clip plays past its duration.
+    pub loop_mode: LoopMode,
+    /// The tracks, each with the index of the instance it animates in
+    /// `Scene::instances`.
+    pub channels: Vec<(usize, Channel)>,
+}
+
+impl<T: Interpolate> Track<T> {
+    /// Finds the track's value at a time. Before the first key and after the
+    /// last, the nearest key's value is held. A periodic track is treated as
+    /// repeating, with its last key the same as its first, which makes cubic
+    /// tracks loop smoothly.
+    pub fn sample(&self, time: f32, periodic: bool) -> Option<T> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+
+        // The segment between keys `i` and `i + 1` contains the time.
+        let i = self.keys.partition_point(|k| k.time <= time) - 1;
+        let (k1, k2) = (self.keys[i], self.keys[i + 1]);
+        let u = (time - k1.time) / (k2.time - k1.time);
+        Some(match self.interpolation {
+            Interpolation::Step => k1.value,
+            Interpolation::Linear => k1.value.interpolate(k2.value, u),
+            Interpolation::Cubic => {
+                let k0 = self.neighbor(i, -1, periodic);
+                let k3 = self.neighbor(i + 1, 1, periodic);
+                catmull_rom([k0, k1, k2, k3], time)
+            }
+        })
+    }
+
+    /// Finds the key before or after key `i`. Past the ends of a periodic
+    /// track this wraps around, and otherwise it repeats the end key's value
+    /// as far away as the key on its other side.
+    fn neighbor(&self, i: usize, step: isize, periodic: bool) -> Keyframe<T> {
+        let n = self.keys.len();
+        let j = i as isize + step;
+        if (0..n as isize).contains(&j) {
+            return self.keys[j as usize];
+        }
+        if periodic {
+            let period = self.keys[n - 1].time - self.keys[0].time;
+            let wrapped = self.keys[(j - step * (n as isize - 1)) as usize];
+            Keyframe {
+                time: wrapped.time + step as f32 * period,
+                value: wrapped.value,
+            }
+        } else {
+            let inner = self.keys[(i as isize - step) as usize];
+            Keyframe {
+                time: 2.0 * self.keys[i].time - inner.time,
+                value: self.keys[i].value,
+            }
+        }
+    }
+}
+
+/// Evaluates the Catmull-Rom spline through four keys between the middle two,
+/// using the Barry-Goldman pyramid of interpolations. Needing only
+/// interpolation, it works for rotations as well as vectors, and it accounts
+/// for uneven spacing of the keys.
+fn catmull_rom<T: Interpolate>(k: [Keyframe<T>; 4], time: f32) -> T {
+    let lerp = |a: T, b: T, t0: f32, t1: f32| a.interpolate(b, (time - t0) / (t1 - t0));
+    let [t0, t1, t2, t3] = k.map(|k| k.time);
+    let a1 = lerp(k[0].value, k[1].value, t0, t1);
+    let a2 = lerp(k[1].value, k[2].value, t1, t2);
+    let a3 = lerp(k[2].value, k[3].value, t2, t3);
+    let b1 = lerp(a1, a2, t0, t2);
+    let b2 = lerp(a2, a3, t1, t3);
+    lerp(b1, b2, t1, t2)
+}
+
+impl Clip {
+    /// Maps a time since the clip started to a time within the clip. Clips
+    /// without a positive duration stay at their start.
+    pub fn local_time(&self, time: f32) -> f32 {
+        if self.duration.is_nan() || self.duration <= 0.0 {
+            return 0.0;
+        }
+        match self.loop_mode {
+            LoopMode::Once => time.clamp(0.0, self.duration),
+            LoopMode::Loop => time.rem_euclid(self.duration),
+            LoopMode::PingPong => {
+                let t = time.rem_euclid(2.0 * self.duration);
+                if t > self.duration {
+                    2.0 * self.duration - t
+                } else {
+                    t
+                }
+            }
+        }
+    }
+
+    /// Sets the animated properties of instances to their values at a time
+    /// since the clip started. Properties without tracks are left alone, as
+    /// are tracks of instances that are not in `instances`, which
+    /// `Scene::validate` rejects.
+    pub fn apply(&self, instances: &mut [Instance], time: f32) {
+        let time = self.local_time(time);
+        let periodic = self.loop_mode == LoopMode::Loop;
+        for (index, channel) in self.channels.iter() {
+            let Some(instance) = instances.get_mut(*index) else {
+                continue;
+            };
+            match channel {
+                Channel::Translation(track) => {
+                    if let Some(v) = track.sample(time, periodic) {
+                        instance.translation = v;
+                    }
+                }
+                Channel::Rotation(track) => {
+                    if let Some(q) = track.sample(time, periodic) {
+                        instance.rotation = q;
+                    }
+                }
+                Channel::Scaling(track) => {
+                    if let Some(s) = track.sample(time, periodic) {
+                        instance.scaling = s;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parses animation clips from text, checking that every track animates one
+/// of `instance_count` instances.
+pub fn parse(text: &str, instance_count: usize) -> Result<Vec<Clip>, RenderError> {
+    let mut clips: Vec<Clip> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        parse_line(line, &mut clips, instance_count).map_err(|message| RenderError::Animation {
+            line: number + 1,
+            message,
+        })?;
+    }
+    Ok(clips)
+}
+
+fn parse_line(line: &str, clips: &mut Vec<Clip>, instance_count: usize) -> Result<(), String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let number = |word: &str| {
+        word.parse::<f32>()
+            .map_err(|_| format!("{word} is not a number"))
+    };
+    match words[..] {
+        ["clip", duration, loop_mode] => {
+            let loop_mode = match loop_mode {
+                "once" => LoopMode::Once,
+                "loop" => LoopMode::Loop,
+                "pingpong" => LoopMode::PingPong,
+                other => return Err(format!("unknown loop mode {other}")),
+            };
+            let duration = number(duration)?;
+            if !duration.is_finite() || duration <= 0.0 {
+                return Err(format!("clip duration {duration} is not a positive time"));
+            }
+            clips.push(Clip {
+                duration,
+                loop_mode,
+                channels: Vec::new(),
+            });
+        }
+        ["track", instance, property, interpolation] => {
+            let clip = clips.last_mut().ok_or("track outside of a clip")?;
+            let instance: usize = instance
+                .parse()
+                .map_err(|_| format!("{instance} is not an instance index"))?;
+            if instance >= instance_count {
+                return Err(format!("there is no instance {instance}"));
+            }
+            let interpolation = match interpolation {
+                "step" => Interpolation::Step,
+                "linear" => Interpolation::Linear,
+                "cubic" => Interpolation::Cubic,
+                other => return Err(format!("unknown interpolation {other}")),
+            };
+            let channel = match property {
+                "translation" => Channel::Translation(Track::new(interpolation)),
+                "rotation" => Channel::Rotation(Track::new(interpolation)),
+                "scaling" => Channel::Scaling(Track::new(interpolation)),
+                other => return Err(format!("unknown property {other}")),
+            };
+            clip.channels.push((instance, channel));
+        }
+        ["key", time, x, y, z] => {
+            let (_, channel) = clips
+                .last_mut()
+                .and_then(|clip| clip.channels.last_mut())
+                .ok_or("key outside of a track")?;
+            let time = number(time)?;
+            if !time.is_finite() || time < 0.0 {
+                return Err(format!("key time {time} is not a time within a clip"));
+            }
+            let [x, y, z] = [number(x)?, number(y)?, number(z)?];
+            match channel {
+                Channel::Translation(track) => track.push(time, Vec4::new(x, y, z, 0.0)),
+                Channel::Rotation(track) => {
+                    let angles = Vec3::new(x.to_radians(), y.to_radians(), z.to_radians());
+                    track.push(time, Quat::from_euler(angles))
+                }
+                Channel::Scaling(track) => track.push(time, Vec3::new(x, y, z)),
+            }?;
+        }
+        _ => return Err(format!("cannot parse {line:?}")),
+    }
+    Ok(())
+}
+
+impl<T> Track<T> {
+    fn new(interpolation: Interpolation) -> Self {
+        Track {
+            interpolation,
+            keys: Vec::new(),
+        }
+    }
+
+    /// Appends a key, which must come after the track's other keys.
+    fn push(&mut self, time: f32, value: T) -> Result<(), String> {
+        if self.keys.last().is_some_and(|k| k.time >= time) {
+            return Err(format!("key at {time} is not after the previous key"));
+        }
+        self.keys.push(Keyframe { time, value });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> Option<(usize, String)> {
+        match parse(text, 1) {
+            Err(RenderError::Animation { line, message }) => Some((line, message)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_clips() {
+        let text = "clip 4 pingpong\ntrack 0 rotation linear\nkey 0 0 0 0\nkey 4 0 90 0\n";
+        let clips = parse(text, 1).unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].loop_mode, LoopMode::PingPong);
+        assert_eq!(clips[0].local_time(5.0), 3.0);
+        assert!(parse(include_str!("../animations/default.anim"), 6).is_ok());
+    }
+
+    #[test]
+    fn rejects_durations_that_are_not_positive_and_finite() {
+        for duration in ["nan", "NaN", "inf", "-inf", "0", "-1"] {
+            let text = format!("clip {duration} once");
+            assert!(
+                parse_error(&text).is_some_and(|(line, _)| line == 1),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_key_times_that_are_not_finite() {
+        for time in ["nan", "inf", "-inf", "-1"] {
+            let text =
+                format!("clip 2 once\ntrack 0 translation linear\nkey 0 0 0 0\nkey {time} 1 1 1");
+            assert!(
+                parse_error(&text).is_some_and(|(line, _)| line == 4),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_keys_out_of_order() {
+        let text = "clip 2 loop\ntrack 0 scaling step\nkey 1 1 1 1\nkey 1 2 2 2";
+        assert_eq!(parse_error(text).map(|(line, _)| line), Some(4));
+    }
+
+    #[test]
+    fn clips_without_a_valid_duration_stay_at_their_start() {
+        for duration in [f32::NAN, 0.0, -1.0] {
+            for loop_mode in [LoopMode::Once, LoopMode::Loop, LoopMode::PingPong] {
+                let clip = Clip {
+                    duration,
+                    loop_mode,
+                    channels: Vec::new(),
+                };
+                assert_eq!(clip.local_time(1.5), 0.0);
+            }
+        }
+    }
+}
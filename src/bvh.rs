@@ -0,0 +1,515 @@
+//! Bounding volume hierarchies for finding the primitives a ray may hit
+//! without testing every one of them.
+
+use crate::math::mat::Mat4;
+use crate::math::vec::Vec4;
+use crate::ray::Ray;
+
+/// Nodes with at most this many primitives are never split.
+const MAX_LEAF_SIZE: usize = 2;
+
+/// The number of buckets primitives are sorted into along an axis when
+/// searching for the cheapest split.
+const SAH_BINS: usize = 12;
+
+/// The cost of visiting a node relative to testing one of its primitives.
+const TRAVERSAL_COST: f32 = 1.0;
+
+/// A hierarchy is rebuilt rather than refitted once refitting has grown the
+/// total surface area of its nodes by this factor since it was built.
+const REBUILD_THRESHOLD: f32 = 2.0;
+
+/// An axis aligned bounding box.
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    /// The smallest x, y and z coordinates inside the box.
+    pub min: [f32; 3],
+    /// The largest x, y and z coordinates inside the box.
+    pub max: [f32; 3],
+}
+
+/// A binary tree of bounding boxes over a set of primitives, which are
+/// referred to by their index.
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    /// The nodes of the tree, with the root first. Children always come after
+    /// their parent, and the two children of a node are next to each other.
+    nodes: Vec<Node>,
+    /// Primitive indices, ordered so that each leaf covers a range of them.
+    indices: Vec<usize>,
+    /// The total surface area of the nodes when the tree was built.
+    built_area: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    bounds: Aabb,
+    /// The first of the node's primitives in `indices` for leaves, or the
+    /// index of its first child for interior nodes.
+    first: usize,
+    /// The number of primitives in a leaf, or zero for interior nodes.
+    count: usize,
+}
+
+impl Aabb {
+    /// A box containing nothing, which any union grows from.
+    pub const EMPTY: Self = Self {
+        min: [f32::INFINITY; 3],
+        max: [f32::NEG_INFINITY; 3],
+    };
+
+    /// Finds the smallest box containing a set of points.
+    pub fn from_points<I: IntoIterator<Item = Vec4>>(points: I) -> Self {
+        points.into_iter().fold(Self::EMPTY, |b, p| {
+            b.union(&Self {
+                min: [p[0], p[1], p[2]],
+                max: [p[0], p[1], p[2]],
+            })
+        })
+    }
+
+    /// Finds the smallest box containing this box and another.
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: [0, 1, 2].map(|i| self.min[i].min(other.min[i])),
+            max: [0, 1, 2].map(|i| self.max[i].max(other.max[i])),
+        }
+    }
+
+    /// The point in the middle of the box.
+    pub fn centroid(&self) -> [f32; 3] {
+        [0, 1, 2].map(|i| (self.min[i] + self.max[i]) / 2.0)
+    }
+
+    /// The total area of the box's faces, which is zero for empty boxes.
+    pub fn surface_area(&self) -> f32 {
+        let [x, y, z] = [0, 1, 2].map(|i| (self.max[i] - self.min[i]).max(0.0));
+        2.0 * (x * y + y * z + z * x)
+    }
+
+    /// Finds the box containing this box after transforming it by a matrix.
+    pub fn transform(&self, m: &Mat4) -> Self {
+        if self.min[0] > self.max[0] {
+            return *self;
+        }
+        Self::from_points((0..8).map(|corner| {
+            let [x, y, z] = [0, 1, 2].map(|i| {
+                if corner & (1 << i) == 0 {
+                    self.min[i]
+                } else {
+                    self.max[i]
+                }
+            });
+            *m * Vec4::new(x, y, z, 1.0)
+        }))
+    }
+
+    /// Finds the ray parameter where a ray enters the box, if it does so
+    /// before `max_t`. Rays starting inside the box enter it at zero.
+    pub fn intersect(&self, ray: &Ray, max_t: f32) -> Option<f32> {
+        let (mut near, mut far) = (0.0f32, max_t);
+        for i in 0..3 {
+            let inv = 1.0 / ray.direction[i];
+            let t0 = (self.min[i] - ray.origin[i]) * inv;
+            let t1 = (self.max[i] - ray.origin[i]) * inv;
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        (near <= far).then_some(near)
+    }
+}
+
+impl Bvh {
+    /// Builds a hierarchy over primitives with the given bounds, splitting
+    /// nodes where the surface area heuristic estimates rays are cheapest to
+    /// trace.
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            indices: (0..bounds.len()).collect(),
+            built_area: 0.0,
+        };
+        if !bounds.is_empty() {
+            bvh.nodes.push(Node {
+                bounds: Aabb::EMPTY,
+                first: 0,
+                count: bounds.len(),
+            });
+            bvh.subdivide(0, bounds);
+        }
+        bvh.built_area = bvh.total_area();
+        bvh
+    }
+
+    /// Updates the hierarchy for primitives that have moved, given their new
+    /// bounds. The tree is refitted around the new bounds, or rebuilt when
+    /// refitting has made it much worse or the number of primitives changed.
+    pub fn update(&mut self, bounds: &[Aabb]) {
+        if bounds.len() != self.indices.len() {
+            *self = Self::build(bounds);
+            return;
+        }
+        self.refit(bounds);
+        if self.total_area() > REBUILD_THRESHOLD * self.built_area {
+            *self = Self::build(bounds);
+        }
+    }
+
+    /// Recomputes the bounds of every node from the primitives' new bounds,
+    /// keeping the tree's structure.
+    pub fn refit(&mut self, bounds: &[Aabb]) {
+        // Children come after their parents, so going backwards visits them
+        // first.
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            self.nodes[i].bounds = if node.count > 0 {
+                self.leaf_bounds(&node, bounds)
+            } else {
+                self.nodes[node.first]
+                    .bounds
+                    .union(&self.nodes[node.first + 1].bounds)
+            };
+        }
+    }
+
+    /// The bounds of everything in the hierarchy.
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |node| node.bounds)
+    }
+
+    /// Finds the primitives a ray may hit, nearest nodes first. `test` is
+    /// called with each candidate primitive and the parameter of the nearest
+    /// hit so far, and returns the parameter where the ray hits the primitive
+    /// if that is nearer. Returns the parameter of the nearest hit.
+    pub fn traverse<F>(&self, ray: &Ray, max_t: f32, mut test: F) -> f32
+    where
+        F: FnMut(usize, f32) -> Option<f32>,
+    {
+        let mut nearest = max_t;
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            // Checking again when popping skips nodes that lie behind hits
+            // found since they were pushed.
+            if node.bounds.intersect(ray, nearest).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                for &primitive in &self.indices[node.first..node.first + node.count] {
+                    if let Some(t) = test(primitive, nearest) {
+                        nearest = nearest.min(t);
+                    }
+                }
+                continue;
+            }
+
+            let (a, b) = (node.first, node.first + 1);
+            let ta = self.nodes[a].bounds.intersect(ray, nearest);
+            let tb = self.nodes[b].bounds.intersect(ray, nearest);
+            // Push the farther child first so that the nearer one is visited
+            // first and shrinks `nearest` for the other.
+            match (ta, tb) {
+                (Some(ta), Some(tb)) if ta <= tb => stack.extend([b, a]),
+                (Some(_), Some(_)) => stack.extend([a, b]),
+                (Some(_), None) => stack.push(a),
+                (None, Some(_)) => stack.push(b),
+                (None, None) => {}
+            }
+        }
+        nearest
+    }
+
+    fn leaf_bounds(&self, node: &Node, bounds: &[Aabb]) -> Aabb {
+        self.indices[node.first..node.first + node.count]
+            .iter()
+            .fold(Aabb::EMPTY, |b, &i| b.union(&bounds[i]))
+    }
+
+    fn total_area(&self) -> f32 {
+        self.nodes
+            .iter()
+            .map(|node| node.bounds.surface_area())
+            .sum()
+    }
+
+    /// Computes the bounds of a leaf node and splits it in two if that is
+    /// estimated to make rays cheaper to trace, then does the same for the
+    /// new nodes.
+    fn subdivide(&mut self, i: usize, bounds: &[Aabb]) {
+        let node = self.nodes[i];
+        self.nodes[i].bounds = self.leaf_bounds(&node, bounds);
+        if node.count <= MAX_LEAF_SIZE {
+            return;
+        }
+
+        let range = node.first..node.first + node.count;
+        let Some((axis, split)) = self.find_split(&self.nodes[i], bounds) else {
+            return;
+        };
+
+        // Move the primitives left of the split to the front of the range.
+        let mut mid = range.start;
+        for j in range.clone() {
+            if bounds[self.indices[j]].centroid()[axis] < split {
+                self.indices.swap(mid, j);
+                mid += 1;
+            }
+        }
+        if mid == range.start || mid == range.end {
+            return;
+        }
+
+        let left = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: Aabb::EMPTY,
+            first: range.start,
+            count: mid - range.start,
+        });
+        self.nodes.push(Node {
+            bounds: Aabb::EMPTY,
+            first: mid,
+            count: range.end - mid,
+        });
+        self.nodes[i].first = left;
+        self.nodes[i].count = 0;
+        self.subdivide(left, bounds);
+        self.subdivide(left + 1, bounds);
+    }
+
+    /// Finds the axis and position of the cheapest split of a leaf by the
+    /// surface area heuristic, comparing splits between buckets of
+    /// primitive centroids. Returns `None` when no split is cheaper than
+    /// keeping the leaf.
+    fn find_split(&self, node: &Node, bounds: &[Aabb]) -> Option<(usize, f32)> {
+        let indices = &self.indices[node.first..node.first + node.count];
+        let centroids = Aabb::from_points(indices.iter().map(|&i| {
+            let [x, y, z] = bounds[i].centroid();
+            Vec4::new(x, y, z, 1.0)
+        }));
+
+        let leaf_cost = node.count as f32 * node.bounds.surface_area();
+        let mut best: Option<(usize, f32, f32)> = None;
+        for axis in 0..3 {
+            let (lo, hi) = (centroids.min[axis], centroids.max[axis]);
+            if hi <= lo {
+                continue;
+            }
+            let scale = SAH_BINS as f32 / (hi - lo);
+            let bin_of = |c: f32| (((c - lo) * scale) as usize).min(SAH_BINS - 1);
+
+            let mut bins = [(Aabb::EMPTY, 0usize); SAH_BINS];
+            for &i in indices {
+                let bin = &mut bins[bin_of(bounds[i].centroid()[axis])];
+                bin.0 = bin.0.union(&bounds[i]);
+                bin.1 += 1;
+            }
+
+            // Sweep from the right to find the cost of everything right of
+            // each boundary, then from the left to price each split.
+            let mut right = [(0.0, 0usize); SAH_BINS];
+            let mut acc = (Aabb::EMPTY, 0);
+            for b in (1..SAH_BINS).rev() {
+                acc = (acc.0.union(&bins[b].0), acc.1 + bins[b].1);
+                right[b] = (acc.0.surface_area(), acc.1);
+            }
+            let mut acc = (Aabb::EMPTY, 0);
+            for b in 1..SAH_BINS {
+                acc = (acc.0.union(&bins[b - 1].0), acc.1 + bins[b - 1].1);
+                let (right_area, right_count) = right[b];
+                if acc.1 == 0 || right_count == 0 {
+                    continue;
+                }
+                let cost = acc.0.surface_area() * acc.1 as f32 + right_area * right_count as f32;
+                if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, lo + b as f32 / scale, cost));
+                }
+            }
+        }
+
+        let (axis, split, cost) = best?;
+        (cost + TRAVERSAL_COST * node.bounds.surface_area() < leaf_cost).then_some((axis, split))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::intersect_triangle;
+    use crate::test_util::{brute_force_raycast, Random};
+
+    /// Small triangles scattered through a cube.
+    fn triangles(random: &mut Random, count: usize) -> Vec<[Vec4; 3]> {
+        (0..count)
+            .map(|_| {
+                let center = random.point(10.0);
+                [0; 3].map(|_| center + random.vector(1.0))
+            })
+            .collect()
+    }
+
+    fn bounds(triangles: &[[Vec4; 3]]) -> Vec<Aabb> {
+        triangles.iter().map(|&t| Aabb::from_points(t)).collect()
+    }
+
+    /// Rays from outside the triangles, half of them aimed at a triangle so
+    /// that most of them hit something.
+    fn rays(random: &mut Random, triangles: &[[Vec4; 3]], count: usize) -> Vec<Ray> {
+        (0..count)
+            .map(|i| {
+                let origin = random.point(20.0);
+                let target = if i % 2 == 0 {
+                    let [a, b, c] = triangles[i % triangles.len()];
+                    (a + b + c) / 3.0
+                } else {
+                    random.point(10.0)
+                };
+                Ray::new(origin, target - origin)
+            })
+            .collect()
+    }
+
+    /// Finds the nearest triangle a ray hits through the hierarchy.
+    fn traverse(bvh: &Bvh, triangles: &[[Vec4; 3]], ray: &Ray) -> Option<(usize, f32)> {
+        let mut nearest = None;
+        bvh.traverse(ray, f32::INFINITY, |i, max_t| {
+            let (t, _) = intersect_triangle(ray, triangles[i])?;
+            if t >= max_t {
+                return None;
+            }
+            nearest = Some((i, t));
+            Some(t)
+        });
+        nearest
+    }
+
+    /// Checks that every node contains its children and its primitives, and
+    /// that every primitive is in exactly one leaf.
+    fn check_structure(bvh: &Bvh, bounds: &[Aabb]) {
+        let contains = |outer: &Aabb, inner: &Aabb| {
+            (0..3).all(|i| outer.min[i] <= inner.min[i] && inner.max[i] <= outer.max[i])
+        };
+        let mut seen = vec![0; bounds.len()];
+        for (i, node) in bvh.nodes.iter().enumerate() {
+            if node.count > 0 {
+                for &p in &bvh.indices[node.first..node.first + node.count] {
+                    assert!(contains(&node.bounds, &bounds[p]), "leaf {i}");
+                    seen[p] += 1;
+                }
+            } else {
+                assert!(node.first > i, "children come after their parent");
+                for child in [node.first, node.first + 1] {
+                    assert!(contains(&node.bounds, &bvh.nodes[child].bounds));
+                }
+            }
+        }
+        assert!(seen.iter().all(|&n| n == 1));
+    }
+
+    #[test]
+    fn raycasts_match_brute_force() {
+        let mut random = Random::new(1);
+        let triangles = triangles(&mut random, 500);
+        let bvh = Bvh::build(&bounds(&triangles));
+        check_structure(&bvh, &bounds(&triangles));
+        assert!(bvh.nodes.len() > 1, "the triangles are split");
+
+        let mut hits = 0;
+        for ray in rays(&mut random, &triangles, 200) {
+            let expected = brute_force_raycast(&triangles, &ray);
+            assert_eq!(traverse(&bvh, &triangles, &ray), expected);
+            hits += expected.is_some() as usize;
+        }
+        assert!(hits > 100, "{hits} rays hit");
+    }
+
+    #[test]
+    fn updates_match_fresh_builds() {
+        let mut random = Random::new(2);
+        let mut triangles = triangles(&mut random, 300);
+        let mut bvh = Bvh::build(&bounds(&triangles));
+        // Small moves are refitted, and the large ones later on grow the
+        // tree enough to rebuild it.
+        for step in 1..=8 {
+            let scale = 0.5 * step as f32;
+            for triangle in triangles.iter_mut() {
+                let offset = random.vector(scale);
+                *triangle = triangle.map(|v| v + offset);
+            }
+            let bounds = bounds(&triangles);
+            bvh.update(&bounds);
+            check_structure(&bvh, &bounds);
+            let fresh = Bvh::build(&bounds);
+            for ray in rays(&mut random, &triangles, 50) {
+                let expected = brute_force_raycast(&triangles, &ray);
+                assert_eq!(traverse(&bvh, &triangles, &ray), expected);
+                assert_eq!(traverse(&fresh, &triangles, &ray), expected);
+            }
+        }
+
+        // A different number of primitives needs a new tree.
+        triangles.truncate(10);
+        let bounds = bounds(&triangles);
+        bvh.update(&bounds);
+        check_structure(&bvh, &bounds);
+    }
+
+    #[test]
+    fn empty_hierarchies_hit_nothing() {
+        let bvh = Bvh::build(&[]);
+        let ray = Ray::new(Vec4::new(0.0, 0.0, 0.0, 1.0), Vec4::new(0.0, 0.0, 1.0, 0.0));
+        assert_eq!(bvh.traverse(&ray, 5.0, |_, _| panic!()), 5.0);
+        assert!(bvh.bounds().surface_area() == 0.0);
+    }
+
+    #[test]
+    fn boxes_are_entered_where_rays_cross_them() {
+        let b = Aabb {
+            min: [-1.0; 3],
+            max: [1.0; 3],
+        };
+        let ray = |origin: [f32; 3], direction: [f32; 3]| {
+            let [x, y, z] = origin;
+            let [dx, dy, dz] = direction;
+            Ray::new(Vec4::new(x, y, z, 1.0), Vec4::new(dx, dy, dz, 0.0))
+        };
+        assert_eq!(
+            b.intersect(&ray([-3.0, 0.0, 0.0], [1.0, 0.0, 0.0]), 10.0),
+            Some(2.0)
+        );
+        assert_eq!(
+            b.intersect(&ray([0.0, 0.0, 0.0], [0.0, 1.0, 0.0]), 10.0),
+            Some(0.0)
+        );
+        assert_eq!(
+            b.intersect(&ray([-3.0, 0.0, 0.0], [1.0, 0.0, 0.0]), 1.0),
+            None
+        );
+        assert_eq!(
+            b.intersect(&ray([-3.0, 0.0, 0.0], [-1.0, 0.0, 0.0]), 10.0),
+            None
+        );
+        assert_eq!(
+            b.intersect(&ray([-3.0, 2.0, 0.0], [1.0, 0.0, 0.0]), 10.0),
+            None
+        );
+
+        // A quarter turn about z swaps the extents of x and y.
+        let flat = Aabb {
+            min: [-2.0, -1.0, 0.0],
+            max: [2.0, 1.0, 0.0],
+        };
+        let turned = flat.transform(&Mat4([
+            [0.0, 1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [3.0, 0.0, 0.0, 1.0],
+        ]));
+        assert_eq!(turned.min, [2.0, -2.0, 0.0]);
+        assert_eq!(turned.max, [4.0, 2.0, 0.0]);
+        assert_eq!(Aabb::EMPTY.transform(&Mat4::IDENTITY).min, Aabb::EMPTY.min);
+    }
+}
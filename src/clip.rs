@@ -0,0 +1,270 @@
+//! Clipping of triangles against the view frustum in homogeneous clip space.
+
+use crate::math::vec::Vec4;
+use crate::shader::Varyings;
+
+/// The most vertices a triangle can have after clipping. Each of the six
+/// frustum planes adds at most one vertex to a convex polygon.
+const MAX_POLYGON_VERTICES: usize = 9;
+
+/// A vertex in homogeneous clip space along with the varyings that are
+/// interpolated across a triangle.
+#[derive(Clone, Copy, Debug)]
+pub struct ClipVertex<V> {
+    /// The position in homogeneous clip space.
+    pub position: Vec4,
+    /// The values interpolated across the triangle.
+    pub varyings: V,
+}
+
+/// A convex polygon with a fixed capacity for vertices.
+#[derive(Clone, Copy, Debug)]
+pub struct ClipPolygon<V> {
+    vertices: [ClipVertex<V>; MAX_POLYGON_VERTICES],
+    len: usize,
+}
+
+/// The planes bounding the view frustum in clip space.
+#[derive(Clone, Copy, Debug)]
+enum ClipPlane {
+    Left,
+    Right,
+    Bottom,
+    Top,
+    Near,
+    Far,
+}
+
+impl<V: Varyings> ClipVertex<V> {
+    /// Creates a vertex from its clip space position and varyings.
+    pub fn new(position: Vec4, varyings: V) -> Self {
+        Self { position, varyings }
+    }
+
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Self {
+            position: self.position + (to.position - self.position) * t,
+            varyings: self.varyings.lerp(to.varyings, t),
+        }
+    }
+}
+
+impl<V: Varyings> ClipPolygon<V> {
+    fn empty(filler: ClipVertex<V>) -> Self {
+        Self {
+            vertices: [filler; MAX_POLYGON_VERTICES],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, v: ClipVertex<V>) {
+        self.vertices[self.len] = v;
+        self.len += 1;
+    }
+
+    /// Whether nothing of the triangle is left after clipping.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Splits the polygon into triangles fanning out from the first vertex.
+    pub fn triangles(&self) -> impl Iterator<Item = [ClipVertex<V>; 3]> + '_ {
+        let v = &self.vertices;
+        (1..self.len.saturating_sub(1)).map(move |i| [v[0], v[i], v[i + 1]])
+    }
+}
+
+impl ClipPlane {
+    const ALL: [Self; 6] = [
+        Self::Left,
+        Self::Right,
+        Self::Bottom,
+        Self::Top,
+        Self::Near,
+        Self::Far,
+    ];
+
+    /// A value that is positive when a point is inside the plane, negative
+    /// when it is outside, and varies linearly along an edge.
+    fn signed_distance(self, v: Vec4) -> f32 {
+        match self {
+            Self::Left => v[3] + v[0],
+            Self::Right => v[3] - v[0],
+            Self::Bottom => v[3] + v[1],
+            Self::Top => v[3] - v[1],
+            Self::Near => v[2],
+            Self::Far => v[3] - v[2],
+        }
+    }
+}
+
+/// Clips a triangle against all six frustum planes using the
+/// Sutherland-Hodgman algorithm. The result is empty when the triangle is
+/// entirely outside the frustum.
+pub fn clip_triangle<V: Varyings>(triangle: [ClipVertex<V>; 3]) -> ClipPolygon<V> {
+    let mut polygon = ClipPolygon::empty(triangle[0]);
+    for v in triangle {
+        polygon.push(v);
+    }
+
+    for plane in ClipPlane::ALL {
+        let d = |v: &ClipVertex<V>| plane.signed_distance(v.position);
+        if polygon.vertices[..polygon.len].iter().all(|v| d(v) >= 0.0) {
+            continue;
+        }
+
+        let mut clipped = ClipPolygon::empty(triangle[0]);
+        for i in 0..polygon.len {
+            let current = polygon.vertices[i];
+            let previous = polygon.vertices[(i + polygon.len - 1) % polygon.len];
+            let d_current = d(&current);
+            let d_previous = d(&previous);
+            if (d_current >= 0.0) != (d_previous >= 0.0) {
+                let t = d_previous / (d_previous - d_current);
+                clipped.push(previous.lerp(current, t));
+            }
+            if d_current >= 0.0 {
+                clipped.push(current);
+            }
+        }
+
+        polygon = clipped;
+        if polygon.len < 3 {
+            polygon.len = 0;
+            break;
+        }
+    }
+
+    polygon
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Random;
+
+    /// Clip space vertices whose varyings are their own positions, so that
+    /// interpolated varyings can be checked against the interpolated
+    /// positions.
+    fn vertices(positions: [[f32; 4]; 3]) -> [ClipVertex<[f32; 4]>; 3] {
+        positions.map(|[x, y, z, w]| ClipVertex::new(Vec4::new(x, y, z, w), [x, y, z, w]))
+    }
+
+    /// Checks that every vertex of a clipped polygon is inside the frustum
+    /// and carries the varyings of its position.
+    fn check_inside(polygon: &ClipPolygon<[f32; 4]>) {
+        for v in &polygon.vertices[..polygon.len] {
+            for plane in ClipPlane::ALL {
+                let d = plane.signed_distance(v.position);
+                assert!(d >= -1.0e-5, "{v:?} is outside {plane:?} by {d}");
+            }
+            for i in 0..4 {
+                assert!((v.position[i] - v.varyings[i]).abs() < 1.0e-5, "{v:?}");
+            }
+        }
+    }
+
+    /// The area of a polygon's triangles after the perspective divide.
+    fn area(polygon: &ClipPolygon<[f32; 4]>) -> f32 {
+        polygon
+            .triangles()
+            .map(|t| {
+                let [a, b, c] = t.map(|v| v.position / v.position[3]);
+                ((b - a).cross(c - a))[2].abs() / 2.0
+            })
+            .sum()
+    }
+
+    #[test]
+    fn keeps_triangles_inside_the_frustum() {
+        let triangle = vertices([
+            [-0.5, -0.5, 0.5, 1.0],
+            [0.5, -0.5, 0.5, 1.0],
+            [0.0, 0.5, 0.5, 1.0],
+        ]);
+        let polygon = clip_triangle(triangle);
+        assert_eq!(polygon.len, 3);
+        let triangles: Vec<_> = polygon.triangles().collect();
+        assert_eq!(triangles.len(), 1);
+        for (clipped, original) in triangles[0].iter().zip(triangle) {
+            assert_eq!(clipped.varyings, original.varyings);
+        }
+    }
+
+    #[test]
+    fn drops_triangles_outside_the_frustum() {
+        // Outside a single plane, and behind the camera.
+        for positions in [
+            [
+                [1.5, 0.0, 0.5, 1.0],
+                [3.0, 0.0, 0.5, 1.0],
+                [2.0, 1.0, 0.5, 1.0],
+            ],
+            [
+                [0.0, 0.0, -0.5, 1.0],
+                [1.0, 0.0, -0.5, 1.0],
+                [0.0, 1.0, -0.1, 1.0],
+            ],
+            [
+                [0.0, 0.0, 2.0, 1.0],
+                [0.5, 0.0, 2.0, 1.0],
+                [0.0, 0.5, 2.0, 1.0],
+            ],
+        ] {
+            let polygon = clip_triangle(vertices(positions));
+            assert!(polygon.is_empty());
+            assert_eq!(polygon.triangles().count(), 0);
+        }
+    }
+
+    #[test]
+    fn cuts_triangles_crossing_a_plane() {
+        // One corner is in front of the near plane, which cuts off a
+        // triangle and leaves a quadrilateral.
+        let polygon = clip_triangle(vertices([
+            [-0.5, -0.5, 0.5, 1.0],
+            [0.5, -0.5, 0.5, 1.0],
+            [0.0, 0.5, -0.5, 1.0],
+        ]));
+        assert_eq!(polygon.len, 4);
+        assert_eq!(polygon.triangles().count(), 2);
+        check_inside(&polygon);
+        // The cut runs halfway up the triangle.
+        assert!((area(&polygon) - 0.375).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn cuts_large_triangles_to_the_viewport() {
+        // A triangle covering the whole viewport is cut by its four sides.
+        let polygon = clip_triangle(vertices([
+            [-10.0, -10.0, 0.5, 1.0],
+            [10.0, -10.0, 0.5, 1.0],
+            [0.0, 10.0, 0.5, 1.0],
+        ]));
+        check_inside(&polygon);
+        assert!((area(&polygon) - 4.0).abs() < 1.0e-4, "{}", area(&polygon));
+    }
+
+    #[test]
+    fn clipped_vertices_stay_inside() {
+        let mut random = Random::new(9);
+        let mut clipped = 0;
+        for _ in 0..1000 {
+            let positions = [(); 3].map(|_| {
+                let w = 1.0 + random.next().abs();
+                [
+                    3.0 * random.next(),
+                    3.0 * random.next(),
+                    2.0 * random.next(),
+                    w,
+                ]
+            });
+            let polygon = clip_triangle(vertices(positions));
+            check_inside(&polygon);
+            assert!(polygon.len <= MAX_POLYGON_VERTICES);
+            assert_eq!(polygon.triangles().count(), polygon.len.saturating_sub(2));
+            clipped += (polygon.len > 3) as usize;
+        }
+        assert!(clipped > 100, "{clipped} triangles gained vertices");
+    }
+}
@@ -0,0 +1,110 @@
+//! The clock that advances the simulation in fixed steps of real time.
+
+use std::fmt;
+use std::time::Instant;
+
+/// The simulation time in seconds advanced by each step.
+pub const STEP: f32 = 1.0 / 60.0;
+
+/// The most real time in seconds a single tick accounts for, so that a long
+/// stall is not followed by a burst of steps to catch up.
+const MAX_FRAME_TIME: f32 = 0.25;
+
+/// The fastest and slowest rates the simulation runs at relative to real time.
+const MAX_SCALE: f32 = 16.0;
+const MIN_SCALE: f32 = 1.0 / 16.0;
+
+/// Tracks simulation time, which follows real time scaled by a rate that can
+/// be negative to play backwards. Time only moves in whole steps, with the
+/// real time left over carried to the next tick, so the simulation sees the
+/// same times however fast frames are rendered.
+pub struct Clock {
+    last: Instant,
+    /// Scaled real time not yet taken as steps.
+    accumulator: f32,
+    time: f32,
+    scale: f32,
+    paused: bool,
+}
+
+impl Clock {
+    /// Creates a clock at time zero, running at normal speed.
+    pub fn new() -> Self {
+        Clock {
+            last: Instant::now(),
+            accumulator: 0.0,
+            time: 0.0,
+            scale: 1.0,
+            paused: false,
+        }
+    }
+
+    /// The simulation time in seconds.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Accounts for the real time passed since the last tick, advancing the
+    /// simulation by as many steps as fit in it.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let elapsed = (now - self.last).as_secs_f32().min(MAX_FRAME_TIME);
+        self.last = now;
+        if self.paused {
+            return;
+        }
+
+        self.accumulator += elapsed * self.scale;
+        let steps = (self.accumulator / STEP).trunc();
+        self.accumulator -= steps * STEP;
+        self.time = (self.time + steps * STEP).max(0.0);
+    }
+
+    /// Stops the simulation time, or starts it again.
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.accumulator = 0.0;
+    }
+
+    /// Moves the simulation one step forwards or backwards, pausing it.
+    pub fn step(&mut self, forwards: bool) {
+        self.paused = true;
+        self.accumulator = 0.0;
+        let step = if forwards { STEP } else { -STEP };
+        self.time = (self.time + step).max(0.0);
+    }
+
+    /// Multiplies the rate the simulation runs at, keeping its direction.
+    pub fn speed_up(&mut self, factor: f32) {
+        let rate = (self.scale.abs() * factor).clamp(MIN_SCALE, MAX_SCALE);
+        self.scale = rate.copysign(self.scale);
+    }
+
+    /// Switches between playing forwards and backwards.
+    pub fn reverse(&mut self) {
+        self.scale = -self.scale;
+        self.accumulator = 0.0;
+    }
+
+    /// Goes back to the start of the simulation.
+    pub fn rewind(&mut self) {
+        self.time = 0.0;
+        self.accumulator = 0.0;
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "t = {:.3} s at {}x", self.time, self.scale)?;
+        if self.paused {
+            write!(f, ", paused")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,340 @@
+//! A small DEFLATE compressor producing zlib streams, as PNG files store them.
+//!
+//! Repeated strings are found with hash chains and coded with the fixed
+//! Huffman codes, which need no code tables in the stream. This compresses
+//! rendered frames well enough while staying simple.
+
+/// The farthest back a match may start.
+const WINDOW_SIZE: usize = 32768;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+/// How many earlier positions with the same hash are tried for each match.
+const MAX_CHAIN: usize = 32;
+
+const HASH_BITS: u32 = 15;
+
+/// The first match length of each length code, from code 257.
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// The first distance of each distance code.
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Packs values into bytes starting from the least significant bit, the order
+/// DEFLATE and GIF both use.
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// Writes the low `count` bits of a value, which must be at most 24.
+    pub fn write(&mut self, value: u32, count: u32) {
+        self.buffer |= (value & ((1 << count) - 1)) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which DEFLATE stores from its most significant
+    /// bit.
+    fn write_code(&mut self, code: u32, count: u32) {
+        self.write(code.reverse_bits() >> (32 - count), count);
+    }
+
+    /// Pads the last byte with zeros and returns the bytes written.
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Compresses data into a zlib stream.
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::new();
+    // The header: a 32K window with deflate, and a check value making the
+    // header a multiple of 31.
+    bits.write(0x78, 8);
+    bits.write(0x01, 8);
+    // A single final block with the fixed codes.
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let mut i = 0;
+    while i < data.len() {
+        let (length, distance) = longest_match(data, i, &head, &prev);
+        let step = if length >= MIN_MATCH {
+            write_match(&mut bits, length, distance);
+            length
+        } else {
+            write_literal(&mut bits, data[i] as u32);
+            1
+        };
+        // Every position passed is added to the chains, so that later
+        // matches can start within this one.
+        for j in i..(i + step).min(data.len().saturating_sub(MIN_MATCH - 1)) {
+            let h = hash(&data[j..]);
+            prev[j % WINDOW_SIZE] = head[h];
+            head[h] = j;
+        }
+        i += step;
+    }
+    write_literal(&mut bits, 256);
+
+    let mut bytes = bits.finish();
+    bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    bytes
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let v = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+    (v.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Finds the longest earlier copy of the data at position `i` within the
+/// window, returning its length and how far back it starts.
+fn longest_match(data: &[u8], i: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if i + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+    let max_length = MAX_MATCH.min(data.len() - i);
+    let (mut best_length, mut best_distance) = (0, 0);
+    let mut candidate = head[hash(&data[i..])];
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || i - candidate > WINDOW_SIZE {
+            break;
+        }
+        let length = data[candidate..]
+            .iter()
+            .zip(&data[i..i + max_length])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best_length {
+            (best_length, best_distance) = (length, i - candidate);
+            if length == max_length {
+                break;
+            }
+        }
+        // Stale entries from older positions sharing the slot point forwards
+        // or to themselves, ending the chain.
+        let next = prev[candidate % WINDOW_SIZE];
+        if next >= candidate {
+            break;
+        }
+        candidate = next;
+    }
+    (best_length, best_distance)
+}
+
+/// Writes a literal byte, or the end of block for 256, with the fixed codes.
+fn write_literal(bits: &mut BitWriter, value: u32) {
+    match value {
+        0..=143 => bits.write_code(0x30 + value, 8),
+        144..=255 => bits.write_code(0x190 + value - 144, 9),
+        256..=279 => bits.write_code(value - 256, 7),
+        _ => bits.write_code(0xc0 + value - 280, 8),
+    }
+}
+
+fn write_match(bits: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASES.partition_point(|&base| base as usize <= length) - 1;
+    write_literal(bits, 257 + code as u32);
+    let extra = LENGTH_EXTRA_BITS[code] as u32;
+    bits.write((length - LENGTH_BASES[code] as usize) as u32, extra);
+
+    let code = DISTANCE_BASES.partition_point(|&base| base as usize <= distance) - 1;
+    bits.write_code(code as u32, 5);
+    let extra = DISTANCE_EXTRA_BITS[code] as u32;
+    bits.write((distance - DISTANCE_BASES[code] as usize) as u32, extra);
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    let (mut a, mut b) = (1, 0);
+    // Sums of this many bytes cannot overflow before being reduced.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MODULUS;
+        b %= MODULUS;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Random;
+
+    /// Reads bits in the order `BitWriter` writes them.
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn read(&mut self, count: u32) -> u32 {
+            let mut value = 0;
+            for i in 0..count {
+                let byte = self.bytes[self.position / 8];
+                value |= ((byte >> (self.position % 8)) as u32 & 1) << i;
+                self.position += 1;
+            }
+            value
+        }
+
+        /// Reads a Huffman code bit by bit, most significant first.
+        fn read_code(&mut self, count: u32, code: &mut u32) {
+            for _ in 0..count {
+                *code = *code << 1 | self.read(1);
+            }
+        }
+
+        /// Reads a literal, a length code or the end of block with the
+        /// fixed codes.
+        fn read_symbol(&mut self) -> u32 {
+            let mut code = 0;
+            self.read_code(7, &mut code);
+            if code <= 0x17 {
+                return 256 + code;
+            }
+            self.read_code(1, &mut code);
+            match code {
+                0x30..=0xbf => return code - 0x30,
+                0xc0..=0xc7 => return 280 + code - 0xc0,
+                _ => {}
+            }
+            self.read_code(1, &mut code);
+            144 + code - 0x190
+        }
+    }
+
+    /// Decompresses a zlib stream made of fixed Huffman blocks, checking its
+    /// header and trailer along the way.
+    fn zlib_decompress(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(stream[0] & 0x0f, 8, "compression method is deflate");
+        assert_eq!(u16::from_be_bytes([stream[0], stream[1]]) % 31, 0);
+        let (body, trailer) = stream.split_at(stream.len() - 4);
+        let mut bits = BitReader {
+            bytes: &body[2..],
+            position: 0,
+        };
+        let mut data = Vec::new();
+        loop {
+            let last = bits.read(1);
+            assert_eq!(bits.read(2), 1, "only fixed Huffman blocks are written");
+            loop {
+                let symbol = bits.read_symbol();
+                if symbol < 256 {
+                    data.push(symbol as u8);
+                    continue;
+                } else if symbol == 256 {
+                    break;
+                }
+                let code = (symbol - 257) as usize;
+                let length = LENGTH_BASES[code] as usize
+                    + bits.read(LENGTH_EXTRA_BITS[code] as u32) as usize;
+                let mut code = 0;
+                bits.read_code(5, &mut code);
+                let code = code as usize;
+                let distance = DISTANCE_BASES[code] as usize
+                    + bits.read(DISTANCE_EXTRA_BITS[code] as u32) as usize;
+                assert!(distance <= data.len() && distance <= WINDOW_SIZE);
+                for _ in 0..length {
+                    data.push(data[data.len() - distance]);
+                }
+            }
+            if last == 1 {
+                break;
+            }
+        }
+        assert_eq!(
+            bits.position.div_ceil(8),
+            body.len() - 2,
+            "no trailing bytes"
+        );
+        assert_eq!(trailer, adler32(&data).to_be_bytes());
+        data
+    }
+
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut random = Random::new(seed);
+        (0..len).map(|_| random.byte()).collect()
+    }
+
+    #[test]
+    fn adler32_matches_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"a"), 0x0062_0062);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        // Long enough to need the sums reduced between chunks.
+        assert_eq!(adler32(&[0xff; 100_000]), 0x149a_302c);
+    }
+
+    #[test]
+    fn compressed_data_round_trips() {
+        let mut repetitive = Vec::new();
+        for i in 0..5000u32 {
+            repetitive.extend_from_slice(format!("row {} ", i % 37).as_bytes());
+        }
+        // Runs longer than the longest match, and matches farther back than
+        // the window once the noise repeats.
+        let mut far = noise(40_000, 7);
+        far.extend_from_within(..20_000);
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"ab".to_vec(),
+            b"abcabcabcabc".to_vec(),
+            vec![0; 1000],
+            vec![0xff; 70_000],
+            (0..=255).collect(),
+            repetitive,
+            noise(10_000, 1),
+            far,
+        ];
+        for data in inputs {
+            let stream = zlib_compress(&data);
+            assert_eq!(zlib_decompress(&stream), data, "length {}", data.len());
+        }
+    }
+
+    #[test]
+    fn repeated_data_compresses() {
+        assert!(zlib_compress(&[0; 10_000]).len() < 100);
+        let data = noise(1000, 3).repeat(10);
+        assert!(zlib_compress(&data).len() < 2000);
+    }
+}
@@ -0,0 +1,217 @@
+//! The errors rendering can fail with.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why a scene could not be built or rendered. Models are checked when they
+/// are created by `Model::new`, and scenes by `Scene::validate` before they
+/// are rendered, so that bad indices are reported here instead of panicking
+/// in the middle of a frame.
+#[derive(Debug)]
+pub enum RenderError {
+    /// A triangle of a model refers to a vertex the model does not have.
+    InvalidVertexIndex {
+        /// The index of the triangle in the model.
+        triangle: usize,
+        /// The vertex index the triangle uses.
+        index: usize,
+        /// The number of vertices the model has.
+        count: usize,
+    },
+    /// A triangle of a model refers to a color the model does not have.
+    InvalidColorIndex {
+        /// The index of the triangle in the model.
+        triangle: usize,
+        /// The color index the triangle uses.
+        index: usize,
+        /// The number of colors the model has.
+        count: usize,
+    },
+    /// A triangle of a model refers to texture coordinates the model does
+    /// not have.
+    InvalidUvIndex {
+        /// The index of the triangle in the model.
+        triangle: usize,
+        /// The texture coordinate index the triangle uses.
+        index: usize,
+        /// The number of texture coordinates the model has.
+        count: usize,
+    },
+    /// A triangle of a model has no area, or a corner that is not finite, so
+    /// it has no normal.
+    DegenerateTriangle {
+        /// The index of the triangle in the model.
+        triangle: usize,
+    },
+    /// A model has a different number of normals than triangles.
+    InvalidNormalCount {
+        /// The number of normals.
+        count: usize,
+        /// The number of triangles.
+        triangles: usize,
+    },
+    /// A model of a scene is invalid.
+    InvalidModel {
+        /// The index of the model in the scene.
+        model: usize,
+        /// What is wrong with the model.
+        source: Box<RenderError>,
+    },
+    /// An instance refers to a model the scene does not have.
+    InvalidModelIndex {
+        /// The index of the instance in the scene.
+        instance: usize,
+        /// The model index the instance uses.
+        index: usize,
+        /// The number of models the scene has.
+        count: usize,
+    },
+    /// An instance refers to a material the scene does not have.
+    InvalidMaterialIndex {
+        /// The index of the instance in the scene.
+        instance: usize,
+        /// The material index the instance uses.
+        index: usize,
+        /// The number of materials the scene has.
+        count: usize,
+    },
+    /// An instance's parent does not come before it in the scene.
+    InvalidParent {
+        /// The index of the instance in the scene.
+        instance: usize,
+        /// The index of its parent.
+        parent: usize,
+    },
+    /// The camera's near depth is not positive, or its far depth is not
+    /// finite and beyond the near one, so it has no projection.
+    InvalidCamera {
+        /// The nearest depth seen.
+        near: f32,
+        /// The farthest depth seen.
+        far: f32,
+    },
+    /// A track of an animation clip animates an instance the scene does not
+    /// have.
+    InvalidClipInstance {
+        /// The index of the clip in the scene.
+        clip: usize,
+        /// The instance index the track animates.
+        instance: usize,
+        /// The number of instances the scene has.
+        count: usize,
+    },
+    /// A framebuffer was asked for a number of samples per pixel that is not
+    /// one of `SAMPLE_COUNTS`.
+    UnsupportedSampleCount {
+        /// The number of samples asked for.
+        samples: u32,
+    },
+    /// A line of an animation could not be parsed.
+    Animation {
+        /// The line number, counting from one.
+        line: usize,
+        /// What is wrong with the line.
+        message: String,
+    },
+    /// The window or other output the frame is presented on failed.
+    Backend(String),
+    /// Reading or writing a file failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::InvalidVertexIndex {
+                triangle,
+                index,
+                count,
+            } => write!(
+                f,
+                "triangle {triangle} uses vertex {index} of a model with {count} vertices"
+            ),
+            RenderError::InvalidColorIndex {
+                triangle,
+                index,
+                count,
+            } => write!(
+                f,
+                "triangle {triangle} uses color {index} of a model with {count} colors"
+            ),
+            RenderError::InvalidUvIndex {
+                triangle,
+                index,
+                count,
+            } => write!(
+                f,
+                "triangle {triangle} uses texture coordinates {index} of a model with {count}"
+            ),
+            RenderError::DegenerateTriangle { triangle } => {
+                write!(f, "triangle {triangle} is degenerate")
+            }
+            RenderError::InvalidNormalCount { count, triangles } => {
+                write!(f, "a model with {triangles} triangles has {count} normals")
+            }
+            RenderError::InvalidModel { model, source } => {
+                write!(f, "model {model} is invalid: {source}")
+            }
+            RenderError::InvalidModelIndex {
+                instance,
+                index,
+                count,
+            } => write!(
+                f,
+                "instance {instance} uses model {index} of a scene with {count} models"
+            ),
+            RenderError::InvalidMaterialIndex {
+                instance,
+                index,
+                count,
+            } => write!(
+                f,
+                "instance {instance} uses material {index} of a scene with {count} materials"
+            ),
+            RenderError::InvalidParent { instance, parent } => write!(
+                f,
+                "instance {instance} has parent {parent}, which does not come before it"
+            ),
+            RenderError::InvalidCamera { near, far } => write!(
+                f,
+                "the camera sees depths from {near} to {far}, which is not a positive range"
+            ),
+            RenderError::InvalidClipInstance {
+                clip,
+                instance,
+                count,
+            } => write!(
+                f,
+                "clip {clip} animates instance {instance} of a scene with {count} instances"
+            ),
+            RenderError::UnsupportedSampleCount { samples } => {
+                write!(f, "unsupported sample count {samples}")
+            }
+            RenderError::Animation { line, message } => {
+                write!(f, "animation line {line}: {message}")
+            }
+            RenderError::Backend(message) => write!(f, "backend failed: {message}"),
+            RenderError::Io(e) => write!(f, "I/O failed: {e}"),
+        }
+    }
+}
+
+impl Error for RenderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RenderError::InvalidModel { source, .. } => Some(source.as_ref()),
+            RenderError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RenderError {
+    fn from(e: io::Error) -> Self {
+        RenderError::Io(e)
+    }
+}
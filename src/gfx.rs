@@ -1,9 +1,18 @@
 //! Graphics rendering code.
 
+#[cfg(feature = "sdl")]
 use sdl2::pixels::Color;
+#[cfg(feature = "sdl")]
 use sdl2::rect::Point;
-use sdl2::render::{Canvas, RenderTarget};
-use std::error::Error;
+#[cfg(feature = "sdl")]
+use sdl2::render::{Canvas, RenderTarget, Texture};
+use std::io::{self, Write};
+use std::ops::{Add, Mul};
+use std::time::{Duration, Instant};
+
+use crate::abuffer::{self, ABuffer};
+use crate::error::RenderError;
+use crate::image::Image;
 
 /// Width of canvas in pixels.
 pub const CANVAS_WIDTH: u32 = 640;
@@ -11,148 +20,735 @@ pub const CANVAS_WIDTH: u32 = 640;
 /// Height of canvas in pixels.
 pub const CANVAS_HEIGHT: u32 = 640;
 
-/// An RGB color where the channel values are floating point values between
-/// 0.0 and 1.0, inclusive.
+/// The most fragments an A-buffer can hold.
+pub const MAX_ABUFFER_CAPACITY: usize = abuffer::MAX_CAPACITY;
+
+/// How far the arms of a crosshair reach from its center in canvas pixels.
+#[cfg(feature = "sdl")]
+const CROSSHAIR_SIZE: i32 = 8;
+
+/// An RGBA color where the channel values are floating point values between
+/// 0.0 and 1.0, inclusive. An alpha of 1.0 is fully opaque.
 #[derive(Clone, Copy, Debug)]
 pub struct ColorF32 {
     r: f32,
     g: f32,
     b: f32,
+    a: f32,
 }
 
-/// Allows for iterating through a sequence of interpolations between two
-/// values.
-#[derive(Debug)]
-struct Interpolation {
-    i_end: i32,
-    i: i32,
-    d: f32,
-    a: f32,
+/// Identifies a triangle of an instance in a scene.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PrimitiveId {
+    /// The index of the instance in the scene.
+    pub instance: usize,
+    /// The index of the triangle in the instance's model.
+    pub triangle: usize,
+}
+
+/// Counts of the work done rendering a frame, and the time spent in each
+/// stage of rendering it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    /// Triangles of the scene's models sent to be drawn.
+    pub triangles_submitted: u64,
+    /// Triangles dropped for facing away from the camera.
+    pub triangles_culled: u64,
+    /// Triangles dropped by clipping for lying entirely outside the view.
+    pub triangles_clipped: u64,
+    /// Triangles filled, counting each piece of a triangle split by clipping.
+    pub triangles_rasterized: u64,
+    /// Pixels a fragment shader ran for, or camera rays that hit something
+    /// when ray tracing.
+    pub pixels_shaded: u64,
+    /// Running vertex shaders.
+    pub transform_time: Duration,
+    /// Finding back faces.
+    pub cull_time: Duration,
+    /// Clipping triangles to the view.
+    pub clip_time: Duration,
+    /// Dividing by w and mapping clipped triangles to plane space.
+    pub project_time: Duration,
+    /// Filling triangles and drawing edges, including fragment shading.
+    pub rasterize_time: Duration,
+    /// Ray tracing the frame instead of rasterizing it.
+    pub trace_time: Duration,
+    /// Resolving samples and turning the framebuffer into the final image.
+    pub resolve_time: Duration,
+    /// Drawing the image on the canvas.
+    pub present_time: Duration,
+}
+
+impl RenderStats {
+    /// The names of the columns written by `write_csv_row`.
+    pub const CSV_HEADER: &str = "frame,frame_ms,triangles_submitted,\
+        triangles_culled,triangles_clipped,triangles_rasterized,pixels_shaded,\
+        transform_ms,cull_ms,clip_ms,project_ms,rasterize_ms,trace_ms,resolve_ms,present_ms";
+
+    /// Writes the stats as a line of comma separated values, after the
+    /// frame's number and its total time, with times in milliseconds.
+    pub fn write_csv_row<W: Write>(
+        &self,
+        out: &mut W,
+        frame: u64,
+        frame_time: Duration,
+    ) -> io::Result<()> {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        writeln!(
+            out,
+            "{frame},{:.3},{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}",
+            ms(frame_time),
+            self.triangles_submitted,
+            self.triangles_culled,
+            self.triangles_clipped,
+            self.triangles_rasterized,
+            self.pixels_shaded,
+            ms(self.transform_time),
+            ms(self.cull_time),
+            ms(self.clip_time),
+            ms(self.project_time),
+            ms(self.rasterize_time),
+            ms(self.trace_time),
+            ms(self.resolve_time),
+            ms(self.present_time),
+        )
+    }
+}
+
+/// Returns the time since an instant and moves the instant to now, for timing
+/// consecutive stages of work.
+pub(crate) fn lap(since: &mut Instant) -> Duration {
+    let now = Instant::now();
+    let elapsed = now - *since;
+    *since = now;
+    elapsed
+}
+
+/// An offscreen color and depth buffer that scenes are rendered into before
+/// being presented on a canvas. Pixels are addressed in canvas space. Each
+/// pixel holds one or more samples with their own color and depth, which are
+/// averaged into the resolved color buffer before presenting. Alongside the
+/// depth, each sample records the triangle it came from for picking.
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    samples: u32,
+    colors: Vec<ColorF32>,
+    depths: Vec<f32>,
+    ids: Vec<Option<PrimitiveId>>,
+    resolved: Image,
+    /// Collects blended fragments for compositing at resolve time, when
+    /// order-independent transparency is enabled.
+    abuffer: Option<ABuffer>,
+    /// Counts the work done since the framebuffer was last cleared.
+    stats: RenderStats,
+}
+
+/// The algorithm used to rasterize lines.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineMode {
+    /// Integer Bresenham lines with hard edges.
+    Aliased,
+    /// Xiaolin Wu lines with coverage alpha blended into the color buffer.
+    AntiAliased,
+}
+
+/// How a color is combined with the color already in a framebuffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    /// Replaces the existing color.
+    Opaque,
+    /// Mixes with the existing color in proportion to the alpha.
+    Alpha,
+    /// Adds the color, scaled by its alpha, to the existing color.
+    Additive,
+    /// Multiplies the existing color by the color, faded by its alpha.
+    Multiply,
 }
 
+/// The sample counts a framebuffer supports.
+pub const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+/// The largest number of samples a pixel can have.
+pub const MAX_SAMPLES: usize = 8;
+
+// Sample positions within a pixel for each supported sample count, as offsets
+// from the pixel's center in plane space. These follow the standard rotated
+// grid patterns so that near-horizontal and near-vertical edges get distinct
+// coverage levels.
+const SAMPLE_OFFSETS_1: [(f32, f32); 1] = [(0.0, 0.0)];
+const SAMPLE_OFFSETS_2: [(f32, f32); 2] = [(0.25, -0.25), (-0.25, 0.25)];
+const SAMPLE_OFFSETS_4: [(f32, f32); 4] = [
+    (-0.125, 0.375),
+    (0.375, 0.125),
+    (-0.375, -0.125),
+    (0.125, -0.375),
+];
+const SAMPLE_OFFSETS_8: [(f32, f32); 8] = [
+    (0.0625, 0.1875),
+    (-0.0625, -0.1875),
+    (0.3125, -0.0625),
+    (-0.1875, 0.3125),
+    (-0.3125, -0.3125),
+    (-0.4375, 0.0625),
+    (0.1875, -0.4375),
+    (0.4375, 0.4375),
+];
+
 /// The result of a rendering function.
-pub type RenderResult = Result<(), Box<dyn Error>>;
+pub type RenderResult = Result<(), RenderError>;
 
 impl ColorF32 {
+    /// Creates an opaque color.
     pub const fn new(r: f32, g: f32, b: f32) -> Self {
-        Self { r, g, b }
+        Self { r, g, b, a: 1.0 }
+    }
+
+    /// Creates a color with an alpha, where zero is fully transparent.
+    pub const fn new_rgba(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
     }
-    
+
+    /// The red, green and blue components.
     pub fn rgb(self) -> (f32, f32, f32) {
         (self.r, self.g, self.b)
     }
-    
+
+    /// The opacity, from zero for transparent to one for opaque.
+    pub fn alpha(self) -> f32 {
+        self.a
+    }
+
+    /// The same color with another alpha.
+    pub fn with_alpha(self, a: f32) -> Self {
+        Self { a, ..self }
+    }
+
+    /// Combines this color over a destination color. The result is opaque.
+    pub fn blend_over(self, dst: Self, mode: BlendMode) -> Self {
+        let a = self.a.clamp(0.0, 1.0);
+        let (r, g, b) = match mode {
+            BlendMode::Opaque => (self.r, self.g, self.b),
+            BlendMode::Alpha => (
+                dst.r + (self.r - dst.r) * a,
+                dst.g + (self.g - dst.g) * a,
+                dst.b + (self.b - dst.b) * a,
+            ),
+            BlendMode::Additive => (dst.r + self.r * a, dst.g + self.g * a, dst.b + self.b * a),
+            BlendMode::Multiply => (
+                dst.r * (1.0 - a + self.r * a),
+                dst.g * (1.0 - a + self.g * a),
+                dst.b * (1.0 - a + self.b * a),
+            ),
+        };
+        Self::new(r, g, b)
+    }
+
+    /// Maps a value between 0 and 1 to a false color, running from black
+    /// through blue, cyan, green, yellow and red to white. Values outside the
+    /// range are clamped.
+    pub fn heatmap(t: f32) -> Self {
+        const STOPS: [ColorF32; 7] = [
+            ColorF32::BLACK,
+            ColorF32::BLUE,
+            ColorF32::CYAN,
+            ColorF32::GREEN,
+            ColorF32::YELLOW,
+            ColorF32::RED,
+            ColorF32::WHITE,
+        ];
+        let x = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+        let i = (x as usize).min(STOPS.len() - 2);
+        let f = x - i as f32;
+        STOPS[i] * (1.0 - f) + STOPS[i + 1] * f
+    }
+
+    /// Opaque black.
     pub const BLACK: Self = Self::new(0.0, 0.0, 0.0);
+    /// Opaque white.
     pub const WHITE: Self = Self::new(1.0, 1.0, 1.0);
+    /// Opaque red.
     pub const RED: Self = Self::new(1.0, 0.0, 0.0);
+    /// Opaque green.
     pub const GREEN: Self = Self::new(0.0, 1.0, 0.0);
+    /// Opaque blue.
     pub const BLUE: Self = Self::new(0.0, 0.0, 1.0);
+    /// Opaque yellow.
     pub const YELLOW: Self = Self::new(1.0, 1.0, 0.0);
+    /// Opaque magenta.
     pub const MAGENTA: Self = Self::new(1.0, 0.0, 1.0);
+    /// Opaque cyan.
     pub const CYAN: Self = Self::new(0.0, 1.0, 1.0);
 }
 
-impl Interpolation {
-    fn new(i0: i32, d0: i32, i1: i32, d1: i32) -> Self {
-        let i_end = i1;
-        let i = i0;
-        let d = d0 as f32;
-        let a = ((d1 - d0) as f32) / ((i1 - i0) as f32);
-        Interpolation { i_end, i, d, a }
+impl Framebuffer {
+    /// Creates a framebuffer with a given number of samples per pixel. Fails
+    /// if the number is not one of `SAMPLE_COUNTS`.
+    pub fn new(width: u32, height: u32, samples: u32) -> Result<Self, RenderError> {
+        let mut framebuffer = Self {
+            width,
+            height,
+            samples: 0,
+            colors: Vec::new(),
+            depths: Vec::new(),
+            ids: Vec::new(),
+            resolved: Image::new(width, height),
+            abuffer: None,
+            stats: RenderStats::default(),
+        };
+        framebuffer.set_samples(samples)?;
+        Ok(framebuffer)
     }
 
-    //  fn new_by_y(p0: Vertex, p1: Vertex) -> Self {
-    //      Interpolation::new(p0.y, p0.x, p1.y, p1.x)
-    //  }
-}
+    /// The width in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The number of samples per pixel.
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// Changes the number of samples per pixel. The sample buffers are
+    /// cleared. Fails, leaving the framebuffer as it was, if the number is not
+    /// one of `SAMPLE_COUNTS`.
+    pub fn set_samples(&mut self, samples: u32) -> RenderResult {
+        if !SAMPLE_COUNTS.contains(&samples) {
+            return Err(RenderError::UnsupportedSampleCount { samples });
+        }
+        let len = (self.width * self.height * samples) as usize;
+        self.samples = samples;
+        self.colors = vec![ColorF32::BLACK; len];
+        self.depths = vec![0.0; len];
+        self.ids = vec![None; len];
+        let capacity = self.abuffer.as_ref().map(ABuffer::capacity);
+        self.set_abuffer(capacity);
+        Ok(())
+    }
+
+    /// Enables order-independent transparency with an A-buffer holding up to
+    /// `capacity` fragments, at most `MAX_ABUFFER_CAPACITY`, or disables it
+    /// when `None`.
+    pub fn set_abuffer(&mut self, capacity: Option<usize>) {
+        self.abuffer = capacity.map(|capacity| ABuffer::new(self.colors.len(), capacity));
+    }
 
-impl Iterator for Interpolation {
-    type Item = (i32, i32);
+    /// The number of fragments the A-buffer holds, or `None` if it is
+    /// disabled.
+    pub fn abuffer_capacity(&self) -> Option<usize> {
+        self.abuffer.as_ref().map(ABuffer::capacity)
+    }
+
+    /// The positions of the samples within a pixel, as offsets from the
+    /// pixel's center in plane space.
+    pub fn sample_offsets(&self) -> &'static [(f32, f32)] {
+        match self.samples {
+            1 => &SAMPLE_OFFSETS_1,
+            2 => &SAMPLE_OFFSETS_2,
+            4 => &SAMPLE_OFFSETS_4,
+            _ => &SAMPLE_OFFSETS_8,
+        }
+    }
+
+    /// Fills the color buffer with a color and resets the depth buffer so
+    /// that every depth passes. Fragments waiting in the A-buffer are
+    /// dropped, and the stats are reset for the next frame.
+    pub fn clear(&mut self, color: ColorF32) {
+        self.colors.fill(color);
+        self.depths.fill(0.0);
+        self.ids.fill(None);
+        if let Some(abuffer) = self.abuffer.as_mut() {
+            abuffer.clear();
+        }
+        self.stats = RenderStats::default();
+    }
+
+    /// The counts and timings of the frame drawn since the last clear.
+    pub fn stats(&self) -> &RenderStats {
+        &self.stats
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.i <= self.i_end {
-            let result = (self.i, self.d as i32);
-            self.i += 1;
-            self.d += self.a;
-            Some(result)
+    /// The stats, for the stages of a frame to add to.
+    pub fn stats_mut(&mut self) -> &mut RenderStats {
+        &mut self.stats
+    }
+
+    /// Converts a point from plane space to canvas space. Plane space is like
+    /// canvas space, except the origin is in the middle of the canvas and y
+    /// points up.
+    pub fn plane_to_canvas(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            (self.width as f32) / 2.0 + x,
+            (self.height as f32) / 2.0 - y,
+        )
+    }
+
+    /// Converts a point from canvas space to plane space.
+    pub fn canvas_to_plane(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            x - (self.width as f32) / 2.0,
+            (self.height as f32) / 2.0 - y,
+        )
+    }
+
+    /// Returns the buffer index of a pixel in canvas space, or `None` when the
+    /// pixel is outside of the buffer.
+    pub fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && x < self.width as i32 && y >= 0 && y < self.height as i32 {
+            Some(y as usize * self.width as usize + x as usize)
         } else {
             None
         }
     }
-}
 
-pub fn create_color_sdl(r: f32, g: f32, b: f32) -> Color {
-   let r = 255.0 * r.clamp(0.0, 1.0);
-   let g = 255.0 * g.clamp(0.0, 1.0);
-   let b = 255.0 * b.clamp(0.0, 1.0);
-   Color::RGB(r as u8, g as u8, b as u8)
-}
+    /// The depth of a sample of the pixel at an index. Larger depths are
+    /// nearer.
+    pub fn depth(&self, index: usize, sample: usize) -> f32 {
+        self.depths[index * self.samples as usize + sample]
+    }
 
-pub fn draw_line<T>(canvas: &mut Canvas<T>, p0: Point, p1: Point) -> RenderResult
-where
-    T: RenderTarget,
-{
-    let dx = p1.x - p0.x;
-    let dy = p1.y - p0.y;
-    if dx.abs() > dy.abs() {
-        // Line is oriented horizontally.
-        let interpolation = if p0.x > p1.x {
-            Interpolation::new(p1.x, p1.y, p0.x, p0.y)
-        } else {
-            Interpolation::new(p0.x, p0.y, p1.x, p1.y)
-        };
+    pub(crate) fn set_depth(&mut self, index: usize, sample: usize, depth: f32) {
+        self.depths[index * self.samples as usize + sample] = depth;
+    }
+
+    pub(crate) fn set_id(&mut self, index: usize, sample: usize, id: PrimitiveId) {
+        self.ids[index * self.samples as usize + sample] = Some(id);
+    }
 
-        for (x, y) in interpolation {
-            put_pixel(canvas, Point::new(x, y))?;
+    /// Finds the triangle nearest to the camera at a pixel in canvas space,
+    /// along with its depth there. Only opaque triangles can be picked.
+    pub fn pick(&self, x: i32, y: i32) -> Option<(PrimitiveId, f32)> {
+        let n = self.samples as usize;
+        let start = self.index(x, y)? * n;
+        (start..start + n)
+            .filter_map(|i| Some((self.ids[i]?, self.depths[i])))
+            .max_by(|(_, d), (_, e)| d.total_cmp(e))
+    }
+
+    pub(crate) fn set_color(&mut self, index: usize, sample: usize, color: ColorF32) {
+        self.colors[index * self.samples as usize + sample] = color;
+    }
+
+    /// Blends a color over a sample.
+    pub fn blend(&mut self, index: usize, sample: usize, color: ColorF32, mode: BlendMode) {
+        let c = &mut self.colors[index * self.samples as usize + sample];
+        *c = color.blend_over(*c, mode);
+    }
+
+    /// Blends a fragment over a sample. With an A-buffer, the fragment is
+    /// stored and composited in depth order when the framebuffer is resolved.
+    /// Once the A-buffer is full, fragments fall back to blending immediately,
+    /// which can put them out of order with the fragments already stored.
+    pub fn blend_deferred(
+        &mut self,
+        index: usize,
+        sample: usize,
+        color: ColorF32,
+        depth: f32,
+        mode: BlendMode,
+    ) {
+        let i = index * self.samples as usize + sample;
+        if let Some(abuffer) = self.abuffer.as_mut() {
+            if abuffer.insert(i, color, depth, mode) {
+                return;
+            }
         }
-    } else {
-        // Line is oriented vertically.
-        let interpolation = if p0.y > p1.y {
-            Interpolation::new(p1.y, p1.x, p0.y, p0.x)
-        } else {
-            Interpolation::new(p0.y, p0.x, p1.y, p1.x)
-        };
+        self.colors[i] = color.blend_over(self.colors[i], mode);
+    }
 
-        for (y, x) in interpolation {
-            put_pixel(canvas, Point::new(x, y))?;
+    /// Composites any deferred fragments, then averages the samples of each
+    /// pixel into the resolved color buffer.
+    pub fn resolve(&mut self) {
+        if let Some(abuffer) = self.abuffer.as_mut() {
+            abuffer.resolve(&mut self.colors);
+        }
+
+        let n = self.samples as usize;
+        let scale = 1.0 / n as f32;
+        let pixels = self.resolved.pixels_mut().iter_mut();
+        for (resolved, samples) in pixels.zip(self.colors.chunks_exact(n)) {
+            *resolved = samples.iter().fold(ColorF32::BLACK, |sum, &c| sum + c) * scale;
         }
     }
 
-    Ok(())
+    /// Finds the camera space depth of each pixel from its nearest sample, or
+    /// infinity where nothing was drawn.
+    pub fn camera_depths(&self) -> Vec<f32> {
+        self.depths
+            .chunks_exact(self.samples as usize)
+            .map(|samples| 1.0 / samples.iter().fold(0.0f32, |d, &s| d.max(s)))
+            .collect()
+    }
+
+    /// The resolved color buffer.
+    pub fn image(&self) -> &Image {
+        &self.resolved
+    }
 }
 
-pub fn draw_wireframe_triangle<T>(
+impl Add for ColorF32 {
+    type Output = ColorF32;
+
+    fn add(self, rhs: ColorF32) -> ColorF32 {
+        ColorF32::new_rgba(
+            self.r + rhs.r,
+            self.g + rhs.g,
+            self.b + rhs.b,
+            self.a + rhs.a,
+        )
+    }
+}
+
+impl Mul<f32> for ColorF32 {
+    type Output = ColorF32;
+
+    fn mul(self, rhs: f32) -> ColorF32 {
+        ColorF32::new_rgba(self.r * rhs, self.g * rhs, self.b * rhs, self.a * rhs)
+    }
+}
+
+/// Converts a color to an SDL color, clamping each component.
+#[cfg(feature = "sdl")]
+pub fn create_color_sdl(r: f32, g: f32, b: f32) -> Color {
+    let r = 255.0 * r.clamp(0.0, 1.0);
+    let g = 255.0 * g.clamp(0.0, 1.0);
+    let b = 255.0 * b.clamp(0.0, 1.0);
+    Color::RGB(r as u8, g as u8, b as u8)
+}
+
+/// Draws an image onto a canvas by uploading it to a streaming RGB24 texture
+/// of the same size in one go and copying that onto the canvas. `pixels` holds
+/// the image's bytes on the way, and is kept between frames so that it is not
+/// reallocated.
+#[cfg(feature = "sdl")]
+pub fn present<T>(
     canvas: &mut Canvas<T>,
-    p0: Point,
-    p1: Point,
-    p2: Point,
+    texture: &mut Texture,
+    pixels: &mut Vec<u8>,
+    image: &Image,
 ) -> RenderResult
 where
     T: RenderTarget,
 {
-    draw_line(canvas, p0, p1)?;
-    draw_line(canvas, p1, p2)?;
-    draw_line(canvas, p2, p0)?;
+    image.to_rgb24(pixels);
+    texture
+        .update(None, pixels, 3 * image.width() as usize)
+        .map_err(|e| RenderError::Backend(e.to_string()))?;
+    canvas
+        .copy(texture, None, None)
+        .map_err(RenderError::Backend)?;
+    Ok(())
+}
 
+/// Draws a crosshair centered on a point of a canvas, for marking points in
+/// debug overlays drawn over a presented image.
+#[cfg(feature = "sdl")]
+pub fn draw_crosshair<T>(canvas: &mut Canvas<T>, x: i32, y: i32, color: ColorF32) -> RenderResult
+where
+    T: RenderTarget,
+{
+    canvas.set_draw_color(create_color_sdl(color.r, color.g, color.b));
+    canvas
+        .draw_line(
+            Point::new(x - CROSSHAIR_SIZE, y),
+            Point::new(x + CROSSHAIR_SIZE, y),
+        )
+        .map_err(RenderError::Backend)?;
+    canvas
+        .draw_line(
+            Point::new(x, y - CROSSHAIR_SIZE),
+            Point::new(x, y + CROSSHAIR_SIZE),
+        )
+        .map_err(RenderError::Backend)?;
     Ok(())
 }
 
-/// Converts a point from plane space to canvas space. Plane space is like
-/// canvas space, except the origin is in the middle of the canvas.
-pub fn plane_to_canvas(p: Point) -> Point {
-    Point::new(
-        (CANVAS_WIDTH as i32) / 2 + p.x,
-        (CANVAS_HEIGHT as i32) / 2 - p.y,
-    )
+/// Rasterizes a line between two points in canvas space with the given mode.
+/// For each pixel touched, `plot` is called with the pixel's coordinates, the
+/// pixel's position along the line from 0.0 at `p0` to 1.0 at `p1`, and the
+/// fraction of the pixel the line covers.
+pub fn rasterize_line<F>(mode: LineMode, p0: (f32, f32), p1: (f32, f32), plot: F)
+where
+    F: FnMut(i32, i32, f32, f32),
+{
+    match mode {
+        LineMode::Aliased => rasterize_line_bresenham(p0, p1, plot),
+        LineMode::AntiAliased => rasterize_line_wu(p0, p1, plot),
+    }
 }
 
-/// Draws a point in plane space on the canvas.
-pub fn put_pixel<T>(canvas: &mut Canvas<T>, p: Point) -> RenderResult
+/// Rasterizes a line with Bresenham's algorithm. The endpoints are rounded to
+/// the nearest pixel and every pixel is fully covered.
+fn rasterize_line_bresenham<F>(p0: (f32, f32), p1: (f32, f32), mut plot: F)
 where
-    T: RenderTarget,
+    F: FnMut(i32, i32, f32, f32),
 {
-    let p = plane_to_canvas(p);
-    canvas.draw_point(p)?;
+    let (x0, y0) = (p0.0.round() as i32, p0.1.round() as i32);
+    let (x1, y1) = (p1.0.round() as i32, p1.1.round() as i32);
+    let dx = (x1 - x0).abs();
+    let dy = -(y1 - y0).abs();
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let sy = if y0 < y1 { 1 } else { -1 };
+    let steps = dx.max(-dy).max(1) as f32;
 
-    Ok(())
+    let (mut x, mut y) = (x0, y0);
+    let mut err = dx + dy;
+    let mut step = 0;
+    loop {
+        plot(x, y, step as f32 / steps, 1.0);
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+        step += 1;
+    }
+}
+
+/// Rasterizes an anti-aliased line with Xiaolin Wu's algorithm. Each step
+/// along the major axis plots the two pixels straddling the line, weighted by
+/// their distance to it. The endpoints keep their sub-pixel positions.
+fn rasterize_line_wu<F>(p0: (f32, f32), p1: (f32, f32), mut plot: F)
+where
+    F: FnMut(i32, i32, f32, f32),
+{
+    let fpart = |v: f32| v - v.floor();
+    let rfpart = |v: f32| 1.0 - fpart(v);
+
+    let steep = (p1.1 - p0.1).abs() > (p1.0 - p0.0).abs();
+    let (mut a, mut b) = if steep {
+        ((p0.1, p0.0), (p1.1, p1.0))
+    } else {
+        (p0, p1)
+    };
+    let reversed = a.0 > b.0;
+    if reversed {
+        std::mem::swap(&mut a, &mut b);
+    }
+
+    let dx = b.0 - a.0;
+    let dy = b.1 - a.1;
+    let gradient = if dx == 0.0 { 1.0 } else { dy / dx };
+
+    // Maps a position along the major axis to the position along the line
+    // from the caller's first point.
+    let along = |x: f32| {
+        let t = if dx == 0.0 {
+            0.0
+        } else {
+            ((x - a.0) / dx).clamp(0.0, 1.0)
+        };
+        if reversed {
+            1.0 - t
+        } else {
+            t
+        }
+    };
+    let mut put = |major: i32, minor: i32, coverage: f32| {
+        let t = along(major as f32);
+        if steep {
+            plot(minor, major, t, coverage);
+        } else {
+            plot(major, minor, t, coverage);
+        }
+    };
+
+    // First endpoint.
+    let x_end = a.0.round();
+    let y_end = a.1 + gradient * (x_end - a.0);
+    let x_gap = rfpart(a.0 + 0.5);
+    let x_first = x_end as i32;
+    put(x_first, y_end.floor() as i32, rfpart(y_end) * x_gap);
+    put(x_first, y_end.floor() as i32 + 1, fpart(y_end) * x_gap);
+    let mut y_intersect = y_end + gradient;
+
+    // Second endpoint.
+    let x_end = b.0.round();
+    let y_end = b.1 + gradient * (x_end - b.0);
+    let x_gap = fpart(b.0 + 0.5);
+    let x_last = x_end as i32;
+    put(x_last, y_end.floor() as i32, rfpart(y_end) * x_gap);
+    put(x_last, y_end.floor() as i32 + 1, fpart(y_end) * x_gap);
+
+    for x in (x_first + 1)..x_last {
+        put(x, y_intersect.floor() as i32, rfpart(y_intersect));
+        put(x, y_intersect.floor() as i32 + 1, fpart(y_intersect));
+        y_intersect += gradient;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_color(c: ColorF32, (r, g, b): (f32, f32, f32)) {
+        let (cr, cg, cb) = c.rgb();
+        assert!(
+            (cr - r).abs() < 1.0e-6 && (cg - g).abs() < 1.0e-6 && (cb - b).abs() < 1.0e-6,
+            "{c:?} is not ({r}, {g}, {b})"
+        );
+    }
+
+    #[test]
+    fn full_abuffer_falls_back_to_immediate_blending() {
+        let mut framebuffer = Framebuffer::new(1, 1, 1).unwrap();
+        framebuffer.set_abuffer(Some(1));
+        let far = ColorF32::RED.with_alpha(0.5);
+        let near = ColorF32::GREEN.with_alpha(0.5);
+        // The far fragment takes the only node, so the near one is blended
+        // straight away and the far one ends up over it.
+        framebuffer.blend_deferred(0, 0, far, 0.1, BlendMode::Alpha);
+        framebuffer.blend_deferred(0, 0, near, 0.9, BlendMode::Alpha);
+        framebuffer.resolve();
+        assert_color(framebuffer.image().pixels()[0], (0.5, 0.25, 0.0));
+
+        // With room for both, they are composited in depth order.
+        framebuffer.set_abuffer(Some(2));
+        framebuffer.clear(ColorF32::BLACK);
+        framebuffer.blend_deferred(0, 0, near, 0.9, BlendMode::Alpha);
+        framebuffer.blend_deferred(0, 0, far, 0.1, BlendMode::Alpha);
+        framebuffer.resolve();
+        assert_color(framebuffer.image().pixels()[0], (0.25, 0.5, 0.0));
+    }
+
+    #[test]
+    fn clearing_drops_unresolved_fragments() {
+        let mut framebuffer = Framebuffer::new(1, 1, 1).unwrap();
+        framebuffer.set_abuffer(Some(16));
+        framebuffer.blend_deferred(0, 0, ColorF32::RED, 0.5, BlendMode::Alpha);
+        framebuffer.clear(ColorF32::BLACK);
+        framebuffer.resolve();
+        assert_color(framebuffer.image().pixels()[0], (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn unsupported_sample_counts_are_rejected() {
+        assert!(matches!(
+            Framebuffer::new(1, 1, 3),
+            Err(RenderError::UnsupportedSampleCount { samples: 3 })
+        ));
+        let mut framebuffer = Framebuffer::new(1, 1, 4).unwrap();
+        assert!(framebuffer.set_samples(0).is_err());
+        assert_eq!(framebuffer.samples(), 4);
+    }
+
+    #[test]
+    fn abuffer_capacity_is_limited() {
+        let mut framebuffer = Framebuffer::new(1, 1, 1).unwrap();
+        framebuffer.set_abuffer(Some(usize::MAX));
+        assert_eq!(framebuffer.abuffer_capacity(), Some(MAX_ABUFFER_CAPACITY));
+    }
 }
@@ -0,0 +1,307 @@
+//! Writing animated GIF files.
+//!
+//! Each frame gets its own palette, chosen by median cut, and its pixels are
+//! dithered to the palette and compressed with LZW as the format requires.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::deflate::BitWriter;
+use crate::image::Image;
+use crate::palette::Palette;
+
+/// The most colors a GIF palette holds.
+const MAX_COLORS: usize = 256;
+
+/// LZW codes are at most this many bits long.
+const MAX_CODE_BITS: u32 = 12;
+
+/// The largest block of data GIF allows between length bytes.
+const MAX_SUB_BLOCK: usize = 255;
+
+/// Writes frames of an animation that loops forever.
+pub struct GifWriter<W: Write> {
+    out: W,
+    width: u16,
+    height: u16,
+    rgb: Vec<u8>,
+    indices: Vec<u8>,
+}
+
+impl<W: Write> GifWriter<W> {
+    /// Writes the header of an animation of a given size.
+    pub fn new(mut out: W, width: u32, height: u32) -> io::Result<Self> {
+        let size = |v: u32| {
+            u16::try_from(v)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "image too large for GIF"))
+        };
+        let (width, height) = (size(width)?, size(height)?);
+
+        out.write_all(b"GIF89a")?;
+        // The logical screen, without a global color table.
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        out.write_all(&[0, 0, 0])?;
+        // The application extension that makes the animation loop, with a
+        // loop count of zero for forever.
+        out.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+        Ok(GifWriter {
+            out,
+            width,
+            height,
+            rgb: Vec::new(),
+            indices: Vec::new(),
+        })
+    }
+
+    /// Appends a frame shown for `delay` hundredths of a second. The image
+    /// must be the size of the animation.
+    pub fn write_frame(&mut self, image: &Image, delay: u16) -> io::Result<()> {
+        if image.width() != self.width as u32 || image.height() != self.height as u32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame size differs from the animation's",
+            ));
+        }
+        image.to_rgb24(&mut self.rgb);
+        let mut palette = Palette::median_cut(&self.rgb, MAX_COLORS);
+        palette.map(&self.rgb, self.width as usize, &mut self.indices);
+        // The color table holds a power of two colors, at least two.
+        let table_bits = palette
+            .colors()
+            .len()
+            .max(2)
+            .next_power_of_two()
+            .trailing_zeros();
+
+        let out = &mut self.out;
+        // The graphic control extension, which leaves each frame in place
+        // for the next to be drawn over.
+        out.write_all(&[0x21, 0xf9, 4, 1 << 2])?;
+        out.write_all(&delay.to_le_bytes())?;
+        out.write_all(&[0, 0])?;
+
+        // The image descriptor, covering the whole screen with a local color
+        // table.
+        out.write_all(&[0x2c, 0, 0, 0, 0])?;
+        out.write_all(&self.width.to_le_bytes())?;
+        out.write_all(&self.height.to_le_bytes())?;
+        out.write_all(&[0x80 | (table_bits - 1) as u8])?;
+        for i in 0..1 << table_bits {
+            let color = palette.colors().get(i).copied().unwrap_or([0; 3]);
+            out.write_all(&color)?;
+        }
+
+        let min_code_bits = table_bits.max(2);
+        out.write_all(&[min_code_bits as u8])?;
+        let data = lzw_compress(&self.indices, min_code_bits);
+        for block in data.chunks(MAX_SUB_BLOCK) {
+            out.write_all(&[block.len() as u8])?;
+            out.write_all(block)?;
+        }
+        out.write_all(&[0])
+    }
+
+    /// Writes the end of the file and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3b])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Compresses palette indices with GIF's variant of LZW. Codes start one bit
+/// longer than `min_code_bits` and grow as the table of strings fills, which
+/// is cleared once the longest codes run out.
+fn lzw_compress(indices: &[u8], min_code_bits: u32) -> Vec<u8> {
+    let clear = 1u32 << min_code_bits;
+    let end = clear + 1;
+    let mut bits = BitWriter::new();
+    let mut code_bits = min_code_bits + 1;
+    let mut next_code = end + 1;
+    // Codes of strings longer than one index, by the code of the string
+    // without its last index and that index.
+    let mut table: HashMap<(u32, u8), u32> = HashMap::new();
+
+    bits.write(clear, code_bits);
+    let Some((&first, rest)) = indices.split_first() else {
+        bits.write(end, code_bits);
+        return bits.finish();
+    };
+    let mut prefix = first as u32;
+    for &index in rest {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        bits.write(prefix, code_bits);
+        // The decoder adds a string for every code it reads after the first,
+        // so codes lengthen once the next string would need another bit.
+        if next_code >= 1 << code_bits && code_bits < MAX_CODE_BITS {
+            code_bits += 1;
+        }
+        if next_code < (1 << MAX_CODE_BITS) - 1 {
+            table.insert((prefix, index), next_code);
+            next_code += 1;
+        } else {
+            bits.write(clear, code_bits);
+            table.clear();
+            code_bits = min_code_bits + 1;
+            next_code = end + 1;
+        }
+        prefix = index as u32;
+    }
+    bits.write(prefix, code_bits);
+    if next_code >= 1 << code_bits && code_bits < MAX_CODE_BITS {
+        code_bits += 1;
+    }
+    bits.write(end, code_bits);
+    bits.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::ColorF32;
+    use crate::test_util::Random;
+
+    /// Decompresses GIF LZW data into palette indices.
+    fn lzw_decompress(data: &[u8], min_code_bits: u32) -> Vec<u8> {
+        let clear = 1usize << min_code_bits;
+        let end = clear + 1;
+        let reset = || -> Vec<Vec<u8>> {
+            let mut table: Vec<Vec<u8>> = (0..clear).map(|i| vec![i as u8]).collect();
+            table.extend([Vec::new(), Vec::new()]);
+            table
+        };
+        let mut table = reset();
+        let mut code_bits = min_code_bits + 1;
+        let mut previous: Option<usize> = None;
+        let mut position = 0;
+        let mut indices = Vec::new();
+        loop {
+            let mut code = 0;
+            for i in 0..code_bits as usize {
+                let bit = data[(position + i) / 8] >> ((position + i) % 8) & 1;
+                code |= (bit as usize) << i;
+            }
+            position += code_bits as usize;
+            if code == clear {
+                table = reset();
+                code_bits = min_code_bits + 1;
+                previous = None;
+                continue;
+            } else if code == end {
+                break;
+            }
+            let string = match (table.get(code), previous) {
+                (Some(string), _) => string.clone(),
+                // The one code that may be used as it is being defined.
+                (None, Some(previous)) if code == table.len() => {
+                    let mut string = table[previous].clone();
+                    string.push(string[0]);
+                    string
+                }
+                _ => panic!("code {code} used before it was defined"),
+            };
+            indices.extend_from_slice(&string);
+            if let Some(previous) = previous {
+                if table.len() < 1 << MAX_CODE_BITS {
+                    let mut added = table[previous].clone();
+                    added.push(string[0]);
+                    table.push(added);
+                }
+            }
+            if table.len() == 1 << code_bits && code_bits < MAX_CODE_BITS {
+                code_bits += 1;
+            }
+            previous = Some(code);
+        }
+        assert_eq!(position.div_ceil(8), data.len(), "no trailing bytes");
+        indices
+    }
+
+    fn noise_image(width: u32, height: u32, seed: u32) -> Image {
+        let mut image = Image::new(width, height);
+        let mut random = Random::new(seed);
+        for pixel in image.pixels_mut() {
+            *pixel = ColorF32::new(random.fraction(), random.fraction(), random.fraction());
+        }
+        image
+    }
+
+    #[test]
+    fn lzw_data_round_trips() {
+        let mut random = Random::new(5);
+        let noise: Vec<u8> = (0..20_000).map(|_| random.byte()).collect();
+        let cases = [
+            (Vec::new(), 2),
+            (vec![0], 2),
+            (vec![1, 1, 1, 1, 1, 1, 1], 2),
+            ([0, 1, 2, 3].repeat(500), 2),
+            (vec![7; 10_000], 3),
+            // Enough strings to fill the table and clear it several times.
+            (noise.iter().map(|v| v & 0x0f).collect(), 4),
+            (noise, 8),
+        ];
+        for (indices, min_code_bits) in cases {
+            let data = lzw_compress(&indices, min_code_bits);
+            assert_eq!(lzw_decompress(&data, min_code_bits), indices);
+        }
+    }
+
+    #[test]
+    fn writes_well_formed_animations() {
+        let (width, height) = (40, 30);
+        let frames = [noise_image(width, height, 1), noise_image(width, height, 2)];
+        let mut writer = GifWriter::new(Vec::new(), width, height).unwrap();
+        for frame in &frames {
+            writer.write_frame(frame, 4).unwrap();
+        }
+        let file = writer.finish().unwrap();
+
+        assert_eq!(&file[..6], b"GIF89a");
+        assert_eq!(&file[6..13], [40, 0, 30, 0, 0, 0, 0]);
+        assert_eq!(
+            &file[13..32],
+            b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00"
+        );
+        let mut rest = &file[32..];
+        for frame in &frames {
+            assert_eq!(&rest[..8], [0x21, 0xf9, 4, 1 << 2, 4, 0, 0, 0]);
+            assert_eq!(&rest[8..17], [0x2c, 0, 0, 0, 0, 40, 0, 30, 0]);
+            let packed = rest[17];
+            assert_eq!(packed & 0xf8, 0x80, "a local color table only");
+            let table_size = 2 << (packed & 7);
+            let table = &rest[18..18 + 3 * table_size];
+            rest = &rest[18 + 3 * table_size..];
+
+            let min_code_bits = rest[0] as u32;
+            rest = &rest[1..];
+            let mut data = Vec::new();
+            while rest[0] != 0 {
+                let length = rest[0] as usize;
+                data.extend_from_slice(&rest[1..1 + length]);
+                rest = &rest[1 + length..];
+            }
+            rest = &rest[1..];
+
+            let mut rgb = Vec::new();
+            frame.to_rgb24(&mut rgb);
+            let mut palette = Palette::median_cut(&rgb, MAX_COLORS);
+            let mut expected = Vec::new();
+            palette.map(&rgb, width as usize, &mut expected);
+            let colors = palette.colors().concat();
+            assert_eq!(&table[..colors.len()], colors);
+            assert_eq!(lzw_decompress(&data, min_code_bits), expected);
+        }
+        assert_eq!(rest, [0x3b]);
+    }
+
+    #[test]
+    fn rejects_frames_of_the_wrong_size() {
+        assert!(GifWriter::new(Vec::new(), 70_000, 1).is_err());
+        let mut writer = GifWriter::new(Vec::new(), 4, 4).unwrap();
+        assert!(writer.write_frame(&Image::new(4, 5), 1).is_err());
+    }
+}
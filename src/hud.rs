@@ -0,0 +1,76 @@
+//! The heads-up display of frame stats drawn over frames in the window.
+
+use std::time::Duration;
+
+use crate::font;
+use crate::gfx::*;
+use crate::image::Image;
+use crate::render::RenderOptions;
+
+/// The size of each pixel of the HUD's font in canvas pixels.
+const HUD_SCALE: u32 = 2;
+
+/// The space in canvas pixels around the HUD's text.
+const HUD_PADDING: u32 = 6;
+
+/// Draws the stats of the last rendered frame, and how long it took from
+/// start to finish, in the top left corner of an image, followed by lines of
+/// status from the caller.
+pub fn draw_hud(
+    image: &mut Image,
+    stats: &RenderStats,
+    frame_time: Duration,
+    options: &RenderOptions,
+    status: &[String],
+) {
+    let ms = |d: Duration| d.as_secs_f32() * 1000.0;
+    let fps = 1.0 / frame_time.as_secs_f32();
+    let mut lines = vec![
+        format!("{fps:.1} fps, {:.1} ms", ms(frame_time)),
+        format!("triangles: {} submitted", stats.triangles_submitted),
+        format!(
+            "{} culled, {} clipped",
+            stats.triangles_culled, stats.triangles_clipped
+        ),
+        format!("{} rasterized", stats.triangles_rasterized),
+        format!("pixels shaded: {}", stats.pixels_shaded),
+        format!(
+            "transform {:.2}, cull {:.2} ms",
+            ms(stats.transform_time),
+            ms(stats.cull_time)
+        ),
+        format!(
+            "clip {:.2}, project {:.2} ms",
+            ms(stats.clip_time),
+            ms(stats.project_time)
+        ),
+        format!(
+            "rasterize {:.2}, trace {:.2} ms",
+            ms(stats.rasterize_time),
+            ms(stats.trace_time)
+        ),
+        format!(
+            "resolve {:.2}, present {:.2} ms",
+            ms(stats.resolve_time),
+            ms(stats.present_time)
+        ),
+        format!("draw: {:?}", options.draw),
+        format!("cull: {:?}", options.cull_backfaces),
+    ];
+    lines.extend_from_slice(status);
+
+    let width = lines
+        .iter()
+        .map(|line| font::text_size(line, HUD_SCALE).0)
+        .max()
+        .unwrap_or(0);
+    let height = ((lines.len() as u32 - 1) * font::LINE_HEIGHT + font::GLYPH_HEIGHT) * HUD_SCALE;
+    let (width, height) = (width + 2 * HUD_PADDING, height + 2 * HUD_PADDING);
+    let backdrop = ColorF32::BLACK.with_alpha(0.6);
+    font::fill_rect(image, 0, 0, width, height, backdrop, BlendMode::Alpha);
+    for (i, line) in lines.iter().enumerate() {
+        let y = HUD_PADDING + i as u32 * font::LINE_HEIGHT * HUD_SCALE;
+        let x = HUD_PADDING as i32;
+        font::draw_text(image, x, y as i32, line, HUD_SCALE, ColorF32::WHITE);
+    }
+}
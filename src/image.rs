@@ -0,0 +1,436 @@
+//! Images and the filters used to resample them.
+
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::deflate::zlib_compress;
+use crate::gfx::ColorF32;
+
+/// A grid of colors stored in rows from top to bottom.
+#[derive(Clone, Debug)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<ColorF32>,
+}
+
+/// A reconstruction filter used when downsampling an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    /// Averages the source pixels that fall within each destination pixel.
+    Box,
+    /// Weights source pixels linearly by distance, reaching into the
+    /// neighboring destination pixels.
+    Tent,
+    /// A windowed sinc with two lobes. Sharper than the tent filter, but it
+    /// can ring around hard edges.
+    Lanczos,
+}
+
+impl Image {
+    /// Creates a black image.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![ColorF32::BLACK; (width * height) as usize],
+        }
+    }
+
+    /// The width in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The pixels in rows from the top.
+    pub fn pixels(&self) -> &[ColorF32] {
+        &self.pixels
+    }
+
+    /// The pixels in rows from the top, for writing.
+    pub fn pixels_mut(&mut self) -> &mut [ColorF32] {
+        &mut self.pixels
+    }
+
+    /// Shrinks the image by an integer factor in both dimensions. Each axis is
+    /// filtered separately, so the cost grows with the filter's width rather
+    /// than its area.
+    pub fn downsample(&self, factor: u32, filter: Filter) -> Self {
+        if factor <= 1 {
+            return self.clone();
+        }
+
+        let width = self.width / factor;
+        let height = self.height / factor;
+        let taps_x = filter.taps(self.width, width, factor);
+        let taps_y = filter.taps(self.height, height, factor);
+
+        let mut rows = Image::new(width, self.height);
+        for y in 0..self.height as usize {
+            let src = &self.pixels[y * self.width as usize..][..self.width as usize];
+            let dst = &mut rows.pixels[y * width as usize..][..width as usize];
+            for (d, taps) in dst.iter_mut().zip(taps_x.iter()) {
+                *d = taps
+                    .iter()
+                    .fold(ColorF32::BLACK, |c, &(i, w)| c + src[i] * w);
+            }
+        }
+
+        let mut image = Image::new(width, height);
+        for (y, taps) in taps_y.iter().enumerate() {
+            for x in 0..width as usize {
+                image.pixels[y * width as usize + x] =
+                    taps.iter().fold(ColorF32::BLACK, |c, &(i, w)| {
+                        c + rows.pixels[i * width as usize + x] * w
+                    });
+            }
+        }
+        image
+    }
+
+    /// Writes the image as a binary PPM file.
+    pub fn write_ppm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let mut bytes = Vec::new();
+        self.to_rgb24(&mut bytes);
+        out.write_all(&bytes)?;
+        out.flush()
+    }
+
+    /// Writes the image as an 8-bit RGB PNG file.
+    pub fn write_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        self.to_rgb24(&mut bytes);
+        let filtered = filter_png_rows(&bytes, self.width as usize * 3);
+
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(b"\x89PNG\r\n\x1a\n")?;
+        let mut header = Vec::new();
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        // 8 bits per channel of RGB, deflate compression, adaptive filtering
+        // and no interlacing.
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_png_chunk(&mut out, b"IHDR", &header)?;
+        write_png_chunk(&mut out, b"IDAT", &zlib_compress(&filtered))?;
+        write_png_chunk(&mut out, b"IEND", &[])?;
+        out.flush()
+    }
+
+    /// Converts the image to three bytes per pixel, red first, in rows from
+    /// top to bottom. The bytes replace the contents of `bytes`, so that its
+    /// allocation can be reused.
+    pub fn to_rgb24(&self, bytes: &mut Vec<u8>) {
+        bytes.clear();
+        for c in self.pixels.iter() {
+            let (r, g, b) = c.rgb();
+            bytes.extend_from_slice(&[to_u8(r), to_u8(g), to_u8(b)]);
+        }
+    }
+}
+
+impl Filter {
+    /// The filter's radius in destination pixels.
+    fn radius(self) -> f32 {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.0,
+            Self::Lanczos => 2.0,
+        }
+    }
+
+    /// The filter's weight at a distance in destination pixels.
+    fn weight(self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            Self::Box => {
+                if x < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Self::Tent => (1.0 - x).max(0.0),
+            Self::Lanczos => {
+                let a = self.radius();
+                if x == 0.0 {
+                    1.0
+                } else if x < a {
+                    let px = PI * x;
+                    a * px.sin() * (px / a).sin() / (px * px)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    /// Finds the source pixels and normalized weights that contribute to each
+    /// destination pixel along one axis.
+    fn taps(self, src_len: u32, dst_len: u32, factor: u32) -> Vec<Vec<(usize, f32)>> {
+        let scale = factor as f32;
+        let reach = (self.radius() * scale).ceil() as i32;
+        (0..dst_len)
+            .map(|d| {
+                let center = (d as f32 + 0.5) * scale - 0.5;
+                let first = center.floor() as i32 - reach;
+                let mut taps: Vec<(usize, f32)> = (first..=first + 2 * reach + 1)
+                    .map(|s| {
+                        let w = self.weight((s as f32 - center) / scale);
+                        (s.clamp(0, src_len as i32 - 1) as usize, w)
+                    })
+                    .filter(|&(_, w)| w != 0.0)
+                    .collect();
+                let total: f32 = taps.iter().map(|&(_, w)| w).sum();
+                for tap in taps.iter_mut() {
+                    tap.1 /= total;
+                }
+                taps
+            })
+            .collect()
+    }
+}
+
+/// Writes a grid of values stored in rows from top to bottom as a grayscale
+/// PFM file, which keeps them as unclamped floats.
+pub fn write_pfm<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    values: &[f32],
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    // A negative scale marks the data as little endian.
+    write!(out, "Pf\n{} {}\n-1.0\n", width, height)?;
+    // PFM rows run from bottom to top.
+    for row in values.chunks_exact(width as usize).rev() {
+        for v in row {
+            out.write_all(&v.to_le_bytes())?;
+        }
+    }
+    out.flush()
+}
+
+/// Converts a channel value between 0.0 and 1.0 to a byte.
+pub fn to_u8(v: f32) -> u8 {
+    (255.0 * v.clamp(0.0, 1.0)) as u8
+}
+
+/// Filters rows of RGB bytes for PNG compression, prefixing each row with
+/// its filter type. Each row takes the filter whose output has the smallest
+/// sum of magnitudes, a common guess at what compresses best.
+fn filter_png_rows(bytes: &[u8], stride: usize) -> Vec<u8> {
+    const BYTES_PER_PIXEL: usize = 3;
+    let mut filtered = Vec::with_capacity(bytes.len() + bytes.len() / stride);
+    let zeros = vec![0; stride];
+    let mut candidates = [(); 5].map(|_| Vec::with_capacity(stride));
+    for (y, row) in bytes.chunks_exact(stride).enumerate() {
+        let above = if y > 0 {
+            &bytes[(y - 1) * stride..y * stride]
+        } else {
+            &zeros
+        };
+        for candidate in candidates.iter_mut() {
+            candidate.clear();
+        }
+        for i in 0..stride {
+            let a = if i >= BYTES_PER_PIXEL {
+                row[i - BYTES_PER_PIXEL]
+            } else {
+                0
+            };
+            let b = above[i];
+            let c = if i >= BYTES_PER_PIXEL {
+                above[i - BYTES_PER_PIXEL]
+            } else {
+                0
+            };
+            let x = row[i];
+            candidates[0].push(x);
+            candidates[1].push(x.wrapping_sub(a));
+            candidates[2].push(x.wrapping_sub(b));
+            candidates[3].push(x.wrapping_sub(((a as u16 + b as u16) / 2) as u8));
+            candidates[4].push(x.wrapping_sub(paeth(a, b, c)));
+        }
+        // Bytes are taken as signed, so that small negative differences
+        // count as small.
+        let cost =
+            |row: &Vec<u8>| -> u32 { row.iter().map(|&v| (v as i8).unsigned_abs() as u32).sum() };
+        let costs = candidates.each_ref().map(cost);
+        // Ties go to the earlier filter.
+        let filter =
+            (1..costs.len()).fold(0, |best, f| if costs[f] < costs[best] { f } else { best });
+        filtered.push(filter as u8);
+        filtered.extend_from_slice(&candidates[filter]);
+    }
+    filtered
+}
+
+/// Predicts a byte from its neighbors to the left, above, and above left,
+/// picking whichever is nearest to their linear estimate.
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Writes a PNG chunk: its length, type, data, and the CRC of its type and
+/// data.
+fn write_png_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = !crc32_update(crc32_update(!0, kind), data);
+    out.write_all(&crc.to_be_bytes())
+}
+
+/// Continues a CRC-32 over more bytes, one bit at a time.
+fn crc32_update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Random;
+
+    /// An image whose rows suit different filters: flat, ramps and noise.
+    fn test_image(width: u32, height: u32) -> Image {
+        let mut image = Image::new(width, height);
+        let mut random = Random::new(1);
+        for (i, pixel) in image.pixels_mut().iter_mut().enumerate() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            let noise = random.fraction();
+            *pixel = match y % 4 {
+                0 => ColorF32::new(0.5, 0.25, 1.0),
+                1 => ColorF32::new(x as f32 / width as f32, 0.5, 0.0),
+                2 => ColorF32::new(noise, y as f32 / height as f32, noise),
+                _ => ColorF32::new(0.5, x as f32 / width as f32, noise),
+            };
+        }
+        image
+    }
+
+    /// Reverses `filter_png_rows`, as a PNG decoder would.
+    fn unfilter_png_rows(filtered: &[u8], stride: usize) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        for (y, row) in filtered.chunks_exact(stride + 1).enumerate() {
+            let (filter, row) = (row[0], &row[1..]);
+            for (i, &v) in row.iter().enumerate() {
+                let a = if i >= 3 { bytes[y * stride + i - 3] } else { 0 };
+                let b = if y > 0 {
+                    bytes[(y - 1) * stride + i]
+                } else {
+                    0
+                };
+                let c = if i >= 3 && y > 0 {
+                    bytes[(y - 1) * stride + i - 3]
+                } else {
+                    0
+                };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    4 => paeth(a, b, c),
+                    _ => panic!("unknown filter {filter}"),
+                };
+                bytes.push(v.wrapping_add(predicted));
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn crc32_matches_known_values() {
+        assert_eq!(!crc32_update(!0, b""), 0);
+        assert_eq!(!crc32_update(!0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(!crc32_update(!0, b"IEND"), 0xae42_6082);
+        // Continuing over more bytes is the same as one pass over them all.
+        assert_eq!(
+            crc32_update(crc32_update(!0, b"1234"), b"56789"),
+            crc32_update(!0, b"123456789")
+        );
+    }
+
+    #[test]
+    fn paeth_picks_the_nearest_neighbor() {
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(20, 10, 10), 20);
+        assert_eq!(paeth(10, 20, 30), 10);
+        assert_eq!(paeth(200, 100, 150), 150);
+        // Ties go to the left, then to above, before above-left.
+        assert_eq!(paeth(0, 15, 10), 0);
+        assert_eq!(paeth(15, 0, 10), 0);
+    }
+
+    #[test]
+    fn filtered_rows_round_trip() {
+        let image = test_image(19, 16);
+        let mut bytes = Vec::new();
+        image.to_rgb24(&mut bytes);
+        let stride = 19 * 3;
+        let filtered = filter_png_rows(&bytes, stride);
+        assert_eq!(filtered.len(), bytes.len() + 16);
+        assert_eq!(unfilter_png_rows(&filtered, stride), bytes);
+        // The flat rows need no more than the filter type and zeros after
+        // their first pixel.
+        let first = &filtered[1..stride + 1];
+        assert!(first[3..].iter().all(|&v| v == 0), "{first:?}");
+    }
+
+    #[test]
+    fn png_files_have_valid_chunks() {
+        let image = test_image(13, 9);
+        let path = std::env::temp_dir().join(format!("rstr-test-{}.png", std::process::id()));
+        image.write_png(&path).unwrap();
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&file[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = Vec::new();
+        let mut rest = &file[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + length]);
+            let crc = u32::from_be_bytes(rest[8 + length..12 + length].try_into().unwrap());
+            assert_eq!(crc, !crc32_update(crc32_update(!0, kind), data));
+            chunks.push((kind.to_vec(), data.to_vec()));
+            rest = &rest[12 + length..];
+        }
+
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| kind.as_slice()).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 13, 0, 0, 0, 9, 8, 2, 0, 0, 0]);
+        let mut bytes = Vec::new();
+        image.to_rgb24(&mut bytes);
+        assert_eq!(chunks[1].1, zlib_compress(&filter_png_rows(&bytes, 13 * 3)));
+        assert!(chunks[2].1.is_empty());
+    }
+}
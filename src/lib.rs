@@ -0,0 +1,59 @@
+//! A software rasterizer, with a ray tracer for reference renders.
+//!
+//! A [`Scene`] places [`Model`]s in the world with a graph of [`Instance`]s
+//! and is seen through a [`Camera`]. [`render_scene`] draws it into a
+//! [`Framebuffer`] as [`RenderOptions`] say, and [`render_image`] also
+//! resolves and downsamples the result to an [`Image`]. [`scene::demo`]
+//! builds the scene the viewer shows:
+//!
+//! ```no_run
+//! use rstr::gfx::{CANVAS_HEIGHT, CANVAS_WIDTH};
+//! use rstr::{render_image, scene, Framebuffer, RenderOptions};
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let mut scene = scene::demo()?;
+//!     let options = RenderOptions::default();
+//!     let mut framebuffer = Framebuffer::new(CANVAS_WIDTH, CANVAS_HEIGHT, 1)?;
+//!     scene.animate(1.0);
+//!     render_image(&mut framebuffer, &scene, &options)?.write_png("frame.png")?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Frames can also be written as SVG drawings by [`svg`], recorded as
+//! animations by [`record`], or shown in a terminal by [`terminal`]. The
+//! `sdl` feature, on by default, adds presenting frames in an SDL window.
+
+mod abuffer;
+pub mod animation;
+pub mod bvh;
+pub mod clip;
+pub mod clock;
+mod deflate;
+pub mod error;
+pub mod font;
+pub mod gfx;
+pub mod gif;
+pub mod hud;
+pub mod image;
+pub mod math;
+mod palette;
+pub mod ray;
+pub mod raytrace;
+pub mod record;
+pub mod render;
+pub mod scene;
+pub mod shader;
+pub mod svg;
+pub mod terminal;
+#[cfg(test)]
+mod test_util;
+
+pub use error::RenderError;
+pub use gfx::{BlendMode, ColorF32, Framebuffer};
+pub use image::Image;
+pub use render::{
+    render_image, render_scene, render_scene_with, Draw, Fragment, RenderOptions, Switch,
+};
+pub use scene::{Camera, Instance, Light, Material, Model, ModelTriangle, Scene};
+pub use shader::{FragmentShader, VertexShader};
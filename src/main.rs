@@ -1,506 +1,339 @@
-mod gfx;
-mod math;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::time::{Duration, Instant};
 
-use sdl2;
+#[cfg(feature = "sdl")]
 use sdl2::event::Event;
+#[cfg(feature = "sdl")]
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::Point;
-use sdl2::render::{Canvas, RenderTarget};
-use std::error::Error;
-use std::f32::consts::PI;
-
-use gfx::*;
-use math::plane::*;
-use math::transform::*;
-use math::vec::*;
-use math::mat::*;
-
-const VIEWPORT_WIDTH: f32 = 1.0;
-const VIEWPORT_HEIGHT: f32 = 1.0;
-const D: f32 = 1.0;
-
-#[derive(Clone, Copy, PartialEq)]
-enum Switch {
-    Off,
-    On,
-}
-
-impl Switch {
-    fn toggle(self) -> Self {
-        match self {
-            Self::On => Self::Off,
-            Self::Off => Self::On,
-        }
-    }
-}
-
-struct Camera {
-    translation: Vec4,
-    rotation: Vec3,
-}
-
-#[derive(Clone, Copy)]
-struct Fragment {
-    x: f32,
-    y: f32,
-    depth: f32,
-    r: f32,
-    g: f32,
-    b: f32,
-}
-
-struct Model {
-    vertices: Vec<Vec4>,
-    colors: Vec<ColorF32>,
-    triangles: Vec<ModelTriangle>,
-    normals: Vec<Vec4>,
+#[cfg(feature = "sdl")]
+use sdl2::mouse::MouseButton;
+#[cfg(feature = "sdl")]
+use sdl2::pixels::PixelFormatEnum;
+
+use rstr::animation;
+use rstr::clock::Clock;
+use rstr::gfx::*;
+#[cfg(feature = "sdl")]
+use rstr::hud;
+use rstr::image::*;
+use rstr::record::Recorder;
+use rstr::render::*;
+use rstr::scene::{self, *};
+use rstr::svg::{self, SvgStyle};
+use rstr::terminal::{Terminal, TerminalMode};
+
+/// The number of fragments the A-buffer holds when order-independent
+/// transparency is enabled without a given capacity.
+#[cfg(feature = "sdl")]
+const DEFAULT_ABUFFER_CAPACITY: usize = 1 << 20;
+
+/// The shortest time between frames drawn in a terminal, which keeps slow
+/// connections from being flooded.
+const TERMINAL_FRAME_TIME: Duration = Duration::from_millis(50);
+
+/// The largest supersampling factor, which already makes the framebuffer
+/// sixteen times the size of the canvas.
+const MAX_SUPERSAMPLE: u32 = 4;
+
+/// The byte a terminal in raw mode sends for Ctrl-C.
+const CTRL_C: u8 = 3;
+
+/// The directory recordings are written under when none is given.
+const DEFAULT_RECORDINGS: &str = "recordings";
+
+/// Command line arguments.
+struct Args {
+    /// Samples per pixel for multisample anti-aliasing.
+    samples: u32,
+    /// Factor the frame is rendered larger by before being downsampled.
+    supersample: u32,
+    filter: Filter,
+    /// When given, a single frame is rendered to this file instead of
+    /// opening a window.
+    output: Option<String>,
+    /// When given, the camera space depth of each pixel of a single frame is
+    /// written to this file as a PFM image, with infinity where nothing was
+    /// drawn.
+    depth_output: Option<String>,
+    /// When given, the triangles of a single frame are written to this file
+    /// as an SVG drawing.
+    svg_output: Option<String>,
+    svg_style: SvgStyle,
+    /// The animation time in seconds of the frame rendered to `output`.
+    time: f32,
+    /// When given, these frames of the animation are recorded instead of
+    /// opening a window, frame `n` being at `n / fps` seconds.
+    frames: Option<Range<u64>>,
+    /// The frame rate of recordings made from `frames`.
+    fps: f32,
+    /// The directory recordings are written under, each in its own numbered
+    /// directory.
+    recordings: String,
+    /// A file of animation clips to play instead of the default ones.
+    animation: Option<String>,
+    /// When given, the stats of every frame are logged to this file as
+    /// comma separated values.
+    stats_csv: Option<String>,
+    /// When given, frames are drawn in the terminal in this mode instead of
+    /// in a window.
+    terminal: Option<TerminalMode>,
+    /// Whether the frame rendered to `output`, or the frames recorded, are
+    /// ray traced.
+    raytrace: bool,
+    /// The fragment capacity of the A-buffer for order-independent
+    /// transparency, or `None` to sort triangles instead.
+    abuffer: Option<usize>,
 }
 
-struct ClippingPlanes {
-    near: Plane,
-    left: Plane,
-    right: Plane,
-    bottom: Plane,
-    top: Plane,
-}
-
-struct Scene {
-    models: Vec<Model>,
-    instances: Vec<Instance>,
-    camera: Camera,
-    clipping_planes: ClippingPlanes,
-}
-
-#[derive(Clone)]
-struct ModelTriangle {
-    vertices: [usize; 3],
-    indices_color: [usize; 3],
-}
-
-struct Instance {
-    model_index: usize,
-    translation: Vec4,
-    scaling: Vec3,
-    rotation: Vec3,
-}
-
-impl Fragment {
-    fn slope_by_y(self, to: Self) -> Self {
-        let y_delta = to.y - self.y;
-        Self {
-            x: (to.x - self.x) / y_delta,
-            y: 1.0,
-            depth: (to.depth - self.depth) / y_delta,
-            r: (to.r - self.r) / y_delta,
-            g: (to.g - self.g) / y_delta,
-            b: (to.b - self.b) / y_delta,
-        }
-    }
-
-    fn slope_by_x(self, to: Self) -> Self {
-        let x_delta = to.x - self.x;
-        Self {
-            x: 1.0,
-            y: (to.y - self.y) / x_delta,
-            depth: (to.depth - self.depth) / x_delta,
-            r: (to.r - self.r) / x_delta,
-            g: (to.g - self.g) / x_delta,
-            b: (to.b - self.b) / x_delta,
+fn parse_args() -> Result<Args, Box<dyn Error>> {
+    let mut args = Args {
+        samples: 1,
+        supersample: 1,
+        filter: Filter::Tent,
+        output: None,
+        depth_output: None,
+        svg_output: None,
+        svg_style: SvgStyle::Flat,
+        time: 0.0,
+        frames: None,
+        fps: 25.0,
+        recordings: DEFAULT_RECORDINGS.to_string(),
+        animation: None,
+        stats_csv: None,
+        terminal: None,
+        raytrace: false,
+        abuffer: None,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or(format!("{arg} requires a value"));
+        match arg.as_str() {
+            "--msaa" => {
+                let samples = value()?.parse()?;
+                if !SAMPLE_COUNTS.contains(&samples) {
+                    return Err(format!("unsupported sample count {samples}").into());
+                }
+                args.samples = samples;
+            }
+            "--ssaa" => {
+                let factor = value()?.parse()?;
+                if !(1..=MAX_SUPERSAMPLE).contains(&factor) {
+                    return Err(format!(
+                        "supersampling factor must be between 1 and {MAX_SUPERSAMPLE}"
+                    )
+                    .into());
+                }
+                args.supersample = factor;
+            }
+            "--filter" => {
+                args.filter = match value()?.as_str() {
+                    "box" => Filter::Box,
+                    "tent" => Filter::Tent,
+                    "lanczos" => Filter::Lanczos,
+                    other => return Err(format!("unknown filter {other}").into()),
+                };
+            }
+            "--output" => args.output = Some(value()?),
+            "--depth-output" => args.depth_output = Some(value()?),
+            "--svg" => args.svg_output = Some(value()?),
+            "--svg-style" => {
+                args.svg_style = match value()?.as_str() {
+                    "flat" => SvgStyle::Flat,
+                    "wireframe" => SvgStyle::Wireframe,
+                    "hidden-line" => SvgStyle::HiddenLine,
+                    other => return Err(format!("unknown SVG style {other}").into()),
+                };
+            }
+            "--time" => args.time = value()?.parse()?,
+            "--frames" => {
+                let range = value()?;
+                let (first, last) = range
+                    .split_once("..")
+                    .ok_or(format!("{range} is not a range of frames such as 0..100"))?;
+                args.frames = Some(first.parse()?..last.parse()?);
+            }
+            "--fps" => {
+                let fps: f32 = value()?.parse()?;
+                if fps <= 0.0 {
+                    return Err("frame rate must be positive".into());
+                }
+                args.fps = fps;
+            }
+            "--record" => args.recordings = value()?,
+            "--animation" => args.animation = Some(value()?),
+            "--stats-csv" => args.stats_csv = Some(value()?),
+            "--terminal" => {
+                args.terminal = match value()?.as_str() {
+                    "blocks" => Some(TerminalMode::HalfBlocks),
+                    "ascii" => Some(TerminalMode::Ascii),
+                    other => return Err(format!("unknown terminal mode {other}").into()),
+                };
+            }
+            "--raytrace" => args.raytrace = true,
+            "--abuffer" => {
+                let capacity = value()?.parse()?;
+                if capacity > MAX_ABUFFER_CAPACITY {
+                    return Err(format!(
+                        "A-buffer capacity must be at most {MAX_ABUFFER_CAPACITY}"
+                    )
+                    .into());
+                }
+                args.abuffer = Some(capacity);
+            }
+            _ => return Err(format!("unknown argument {arg}").into()),
         }
     }
+    Ok(args)
 }
 
-impl std::ops::AddAssign for Fragment {
-   fn add_assign(&mut self, rhs: Self) {
-       self.x += rhs.x;
-       self.y += rhs.y;
-       self.depth += rhs.depth;
-       self.r += rhs.r;
-       self.g += rhs.g;
-       self.b += rhs.b;
-   } 
-}
-
-impl Instance {
-    fn new(model_index: usize) -> Self {
-        Instance {
-            model_index,
-            translation: Vec4::new(0.0, 0.0, 0.0, 0.0),
-            scaling: Vec3::new(1.0, 1.0, 1.0),
-            rotation: Vec3::new(0.0, 0.0, 0.0),
-        }
-    }
+/// Creates a CSV file for logging the stats of each frame, starting with a
+/// header.
+fn create_stats_log(path: &str) -> std::io::Result<BufWriter<File>> {
+    let mut log = BufWriter::new(File::create(path)?);
+    writeln!(log, "{}", RenderStats::CSV_HEADER)?;
+    Ok(log)
 }
 
-impl Model {
-    fn new(vertices: Vec<Vec4>, colors: Vec<ColorF32>, triangles: Vec<ModelTriangle>) -> Self {
-        let mut normals = Vec::new();
-        for triangle in triangles.iter() {
-            let v1 = vertices[triangle.vertices[1]] - vertices[triangle.vertices[0]];
-            let v2 = vertices[triangle.vertices[2]] - vertices[triangle.vertices[0]];
-            let normal = v1.cross(v2).normalize();
-            normals.push(normal);
-        }
-        Model {
-            vertices,
-            colors,
-            triangles,
-            normals,
+/// Renders frames in the terminal until q or Ctrl-C is pressed. The d, p and w
+/// keys switch between the depth, pixel and wireframe views, and c toggles
+/// back-face culling, as in the window.
+fn run_terminal(
+    mode: TerminalMode,
+    framebuffer: &mut Framebuffer,
+    scene: &mut Scene,
+    options: &mut RenderOptions,
+) -> Result<(), Box<dyn Error>> {
+    let mut terminal = Terminal::new(mode)?;
+    let mut clock = Clock::new();
+    let mut frame_start = Instant::now();
+    let mut frame_time = Duration::ZERO;
+    loop {
+        while let Some(key) = terminal.poll_key() {
+            match key.to_ascii_lowercase() {
+                b'q' | CTRL_C => return Ok(()),
+                b'd' => options.draw = Draw::Depths,
+                b'p' => options.draw = Draw::Pixels,
+                b'w' => options.draw = Draw::Wireframe,
+                b'c' => options.cull_backfaces = options.cull_backfaces.toggle(),
+                _ => {}
+            }
         }
-    }
-}
 
-impl ModelTriangle {
-    fn new(vertices: [usize; 3], indices_color: [usize; 3]) -> Self {
-        ModelTriangle { vertices, indices_color }
+        clock.tick();
+        scene.animate(clock.time());
+        let image = render_image(framebuffer, scene, options)?;
+        let status = format!(
+            "draw: {:?}, cull: {:?}, {:.1} fps | d/p/w: draw, c: cull, q: quit",
+            options.draw,
+            options.cull_backfaces,
+            1.0 / frame_time.as_secs_f32()
+        );
+        terminal.draw(&image, &status)?;
+
+        std::thread::sleep(TERMINAL_FRAME_TIME.saturating_sub(frame_start.elapsed()));
+        frame_time = frame_start.elapsed();
+        frame_start += frame_time;
     }
 }
 
-fn create_camera_transform(camera: &Camera) -> Mat4 {
-    let c_t = translation(-camera.translation);
-    let c_rx = rotation_x(-camera.rotation[0]);
-    let c_ry = rotation_y(-camera.rotation[1]);
-    let c_rz = rotation_z(-camera.rotation[2]);
-    c_rx * c_ry * c_rz * c_t
-}
-
-fn create_instance_transform(instance: &Instance) -> Mat4 {
-    let i_t = translation(instance.translation);
-    let i_s = scaling(instance.scaling);
-    let i_r = rotation(instance.rotation);
-    i_t * i_r * i_s
-}
-
-fn projected_to_point(v: Vec3, color: ColorF32) -> Fragment {
-    let x = v[0] / v[2];
-    let y = v[1] / v[2];
-    let depth = 1.0 / v[2];
-    let (r, g, b) = color.rgb();
-    Fragment { x, y, depth, r, g, b }
-}
-
-fn i32_range(x: f32, y: f32) -> core::ops::Range<i32> {
-    (x as i32)..(y as i32)
-}
-
-fn i32_range_inclusive(x: f32, y: f32) -> core::ops::RangeInclusive<i32> {
-    (x as i32)..=(y as i32)
-}
-
-fn is_in_canvas(x: i32, y: i32) -> bool {
-    x >= 0 && x < CANVAS_WIDTH as i32 && y >= 0 && y < CANVAS_HEIGHT as i32
-}
-
-#[derive(Clone, Copy)]
-enum Draw {
-    Depths,
-    Pixels,
-    Wireframe,
-}
-
-fn draw_line_horizontal<T>(
-    canvas: &mut Canvas<T>,
-    depth_buffer: &mut [f32],
-    f1: Fragment,
-    f2: Fragment,
-    y: i32,
-    draw: Draw,
-) -> RenderResult
-where
-    T: RenderTarget,
-{
-    let (f_left, f_right) = if f1.x > f2.x {
-        (f2, f1)
-    } else {
-        (f1, f2)
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = parse_args()?;
+    let mut framebuffer = Framebuffer::new(
+        CANVAS_WIDTH * args.supersample,
+        CANVAS_HEIGHT * args.supersample,
+        args.samples,
+    )?;
+    framebuffer.set_abuffer(args.abuffer);
+    let mut stats_log = match &args.stats_csv {
+        Some(path) => Some(create_stats_log(path)?),
+        None => None,
     };
-    let mut f = f_left;
-    let f_slope = f_left.slope_by_x(f_right);
-    for x in i32_range_inclusive(f_left.x, f_right.x) {
-        let p = Point::new(x, y);
-        let p = plane_to_canvas(p);
-        if is_in_canvas(p.x, p.y) {
-            let y = p.y as usize;
-            let x = p.x as usize;
-            let w = CANVAS_WIDTH as usize;
-            let depth_index = y * w + x;
-            if f.depth > depth_buffer[depth_index] {
-                depth_buffer[depth_index] = f.depth;
-                let c = match draw {
-                    Draw::Depths => {
-                        let c = (255.0 * f.depth) as u8;
-                        Color::RGB(c, c, c)
-                    }
-                    _ => create_color_sdl(f.r, f.g, f.b),
-                };
-                canvas.set_draw_color(c);
-                canvas.draw_point(p)?;
-            }
-        }
-        f += f_slope;
+    let mut scene = scene::demo()?;
+    if let Some(path) = &args.animation {
+        let text = std::fs::read_to_string(path)?;
+        scene.clips = animation::parse(&text, scene.instances.len())?;
     }
-    Ok(())
-}
-
-fn render_scene<T>(canvas: &mut Canvas<T>, scene: &Scene, draw: Draw, cull_backfaces: Switch) -> RenderResult
-where
-    T: RenderTarget,
-{
-    let mut depth_buffer = vec![0.0; (CANVAS_WIDTH * CANVAS_HEIGHT) as usize];
-    let m_projection = {
-        let p = perspective_projection(D);
-        let m = viewport_to_canvas(CANVAS_WIDTH, CANVAS_HEIGHT, VIEWPORT_WIDTH, VIEWPORT_HEIGHT);
-        m * p
+    let mut options = RenderOptions {
+        supersample: args.supersample,
+        filter: args.filter,
+        ..RenderOptions::default()
     };
 
-    let camera_transform = create_camera_transform(&scene.camera);
-
-    for instance in scene.instances.iter() {
-        let transform = camera_transform * create_instance_transform(&instance);
-        let model = &scene.models[instance.model_index];
-        for (triangle_index, triangle) in model.triangles.iter().enumerate() {
-            let triangle_data = [
-                model.vertices[triangle.vertices[0]],
-                model.vertices[triangle.vertices[1]],
-                model.vertices[triangle.vertices[2]],
-            ];
-            let colors_triangle = [
-                model.colors[triangle.indices_color[0]],
-                model.colors[triangle.indices_color[1]],
-                model.colors[triangle.indices_color[2]],
-            ];
-            let transformed_triangle_data = [
-                transform * triangle_data[0],
-                transform * triangle_data[1],
-                transform * triangle_data[2],
-            ];
-
-            // back-face culling
-            if cull_backfaces == Switch::On {
-                let normal = model.normals[triangle_index];
-                let transformed_normal = transform * normal;
-                let view_vector = transformed_triangle_data[0]; // camera always at origin.
-                let normal_dot_view = transformed_normal.dot(view_vector);
-                if normal_dot_view >= 0.0 {
-                    continue;
-                }
-            }
-
-            for clipped_triangle in
-                clip_triangle(transformed_triangle_data, &scene.clipping_planes.near)
-            {
-                for clipped_triangle in clip_triangle(clipped_triangle, &scene.clipping_planes.left)
-                {
-                    for clipped_triangle in
-                        clip_triangle(clipped_triangle, &scene.clipping_planes.right)
-                    {
-                        for clipped_triangle in
-                            clip_triangle(clipped_triangle, &scene.clipping_planes.bottom)
-                        {
-                            for clipped_triangle in
-                                clip_triangle(clipped_triangle, &scene.clipping_planes.top)
-                            {
-                                let mut p = [
-                                    projected_to_point(m_projection * clipped_triangle[0], colors_triangle[0]),
-                                    projected_to_point(m_projection * clipped_triangle[1], colors_triangle[1]),
-                                    projected_to_point(m_projection * clipped_triangle[2], colors_triangle[2]),
-                                ];
-
-                                match draw {
-                                    Draw::Wireframe => {
-                                        let color = create_color_sdl(p[0].r, p[0].g, p[0].b);
-                                        canvas.set_draw_color(color);
-                                        let p0 = Point::new(p[0].x as i32, p[0].y as i32);
-                                        let p1 = Point::new(p[1].x as i32, p[1].y as i32);
-                                        let p2 = Point::new(p[2].x as i32, p[2].y as i32);
-                                        draw_wireframe_triangle(canvas, p0, p1, p2)?;
-                                        continue;
-                                    }
-                                    _ => ()
-                                }
-
-                                p.sort_by(|p, q| p.y.total_cmp(&q.y));
-
-                                let mut long = p[0];
-                                let long_slope = p[0].slope_by_y(p[2]);
-                                
-                                for i in 0..=1 {
-                                    let mut short = p[i];
-                                    let short_slope = p[i].slope_by_y(p[i + 1]);
-                                    for y in i32_range(p[i].y, p[i + 1].y) {
-                                        draw_line_horizontal(
-                                            canvas,
-                                            &mut depth_buffer,
-                                            long,
-                                            short,
-                                            y,
-                                            draw,
-                                        )?;
-                                        long += long_slope;
-                                        short += short_slope;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+    if let Some(frames) = args.frames.clone() {
+        if args.raytrace {
+            options.draw = Draw::RayTraced;
+        }
+        let mut recorder = Recorder::new(&args.recordings, CANVAS_WIDTH, CANVAS_HEIGHT)?;
+        let frame_time = Duration::from_secs_f32(1.0 / args.fps);
+        for n in frames {
+            scene.animate(n as f32 / args.fps);
+            let start = Instant::now();
+            let image = render_image(&mut framebuffer, &scene, &options)?;
+            if let Some(log) = &mut stats_log {
+                framebuffer.stats().write_csv_row(log, n, start.elapsed())?;
             }
+            recorder.add_frame(&image, frame_time)?;
         }
-    }
-
-    Ok(())
-}
-
-fn clip_triangle(triangle: [Vec4; 3], plane: &Plane) -> Vec<[Vec4; 3]> {
-    let mut clipped_triangles = Vec::with_capacity(3);
-
-    let d = [
-        plane.signed_distance(triangle[0]),
-        plane.signed_distance(triangle[1]),
-        plane.signed_distance(triangle[2]),
-    ];
-
-    let mut positive = Vec::with_capacity(3);
-    let mut negative = Vec::with_capacity(3);
-
-    for i in 0..3 {
-        if d[i] > 0.0 {
-            positive.push(i);
-        } else {
-            negative.push(i);
+        if let Some(log) = &mut stats_log {
+            log.flush()?;
         }
+        let directory = recorder.directory().to_path_buf();
+        let count = recorder.finish()?;
+        println!("recorded {count} frames to {}", directory.display());
+        return Ok(());
     }
 
-    match positive.len() {
-        3 => {
-            clipped_triangles.push(triangle);
+    if args.output.is_some() || args.depth_output.is_some() || args.svg_output.is_some() {
+        if args.raytrace {
+            options.draw = Draw::RayTraced;
         }
-        2 => {
-            let a = triangle[positive[0]];
-            let b = triangle[positive[1]];
-            let c = triangle[negative[0]];
-
-            let a_prime = plane.intersection(a, c);
-            let b_prime = plane.intersection(b, c);
-
-            clipped_triangles.push([a, b, b_prime]);
-            clipped_triangles.push([a_prime, b, b_prime]);
+        scene.animate(args.time);
+        let start = Instant::now();
+        let image = render_image(&mut framebuffer, &scene, &options)?;
+        if let Some(log) = &mut stats_log {
+            framebuffer.stats().write_csv_row(log, 0, start.elapsed())?;
+            log.flush()?;
         }
-        1 => {
-            let a = triangle[positive[0]];
-            let b = triangle[negative[0]];
-            let c = triangle[negative[1]];
-
-            let b_prime = plane.intersection(a, b);
-            let c_prime = plane.intersection(a, c);
-
-            clipped_triangles.push([a, b_prime, c_prime]);
+        if let Some(path) = &args.output {
+            image.write_ppm(path)?;
         }
-        _ => (),
-    }
-
-    clipped_triangles
-}
-
-fn build_scene() -> Scene {
-    let vertices = vec![
-        Vec4::new(1.0, 1.0, 1.0, 1.0), // 0 - black(0)
-        Vec4::new(-1.0, 1.0, 1.0, 1.0), // 1 - red(0)
-        Vec4::new(-1.0, -1.0, 1.0, 1.0), // 2 - yellow(3)
-        Vec4::new(1.0, -1.0, 1.0, 1.0), // 3 - green(1)
-        Vec4::new(1.0, 1.0, -1.0, 1.0), // 4 - blue(2)
-        Vec4::new(-1.0, 1.0, -1.0, 1.0), // 5 - magenta(4)
-        Vec4::new(-1.0, -1.0, -1.0, 1.0), // 6 - white (0)
-        Vec4::new(1.0, -1.0, -1.0, 1.0), // 7 - cyan(5)
-    ];
-    
-    let colors = vec![
-        ColorF32::RED, // 0
-        ColorF32::GREEN, // 1
-        ColorF32::BLUE, // 2
-        ColorF32::YELLOW, // 3
-        ColorF32::MAGENTA, // 4
-        ColorF32::CYAN, // 5
-        ColorF32::WHITE, // 6
-        ColorF32::BLACK, // 7
-    ];
-
-    let triangles = vec![
-        ModelTriangle::new([0, 1, 2], [7, 0, 3]),
-        ModelTriangle::new([0, 2, 3], [7, 3, 1]),
-        ModelTriangle::new([4, 0, 3], [2, 7, 1]),
-        ModelTriangle::new([4, 3, 7], [2, 1, 5]),
-        ModelTriangle::new([5, 4, 7], [4, 2, 5]),
-        ModelTriangle::new([5, 7, 6], [4, 5, 6]),
-        ModelTriangle::new([1, 5, 6], [0, 4, 6]),
-        ModelTriangle::new([1, 6, 2], [0, 6, 3]),
-        ModelTriangle::new([4, 5, 1], [2, 4, 0]),
-        ModelTriangle::new([4, 1, 0], [2, 0, 7]),
-        ModelTriangle::new([2, 6, 7], [3, 6, 5]),
-        ModelTriangle::new([2, 7, 3], [3, 5, 1]),
-    ];
-
-    let models = vec![Model::new(vertices, colors, triangles)];
-
-    let instances = vec![
-        Instance::new(0),
-        Instance::new(0),
-        Instance::new(0),
-        Instance::new(0),
-    ];
-
-    let camera = Camera {
-        translation: Vec4::new(0.0, 0.0, 0.0, 0.0),
-        rotation: Vec3::new(0.0, 0.0, 0.0),
-    };
-
-    let clipping_planes = {
-        let near = Plane::new(Vec4::new(0.0, 0.0, 1.0, 0.0), -D);
-        let left = Plane::new(Vec4::new(1.0, 0.0, 1.0, 0.0), 0.0);
-        let right = Plane::new(Vec4::new(-1.0, 0.0, 1.0, 0.0), 0.0);
-        let bottom = Plane::new(Vec4::new(0.0, 1.0, 1.0, 0.0), 0.0);
-        let top = Plane::new(Vec4::new(0.0, -1.0, 1.0, 0.0), 0.0);
-        ClippingPlanes {
-            near,
-            left,
-            right,
-            bottom,
-            top,
+        if let Some(path) = &args.depth_output {
+            let depths = framebuffer.camera_depths();
+            write_pfm(path, framebuffer.width(), framebuffer.height(), &depths)?;
         }
-    };
-
-    Scene {
-        models,
-        instances,
-        camera,
-        clipping_planes,
+        if let Some(path) = &args.svg_output {
+            svg::write_scene(path, &mut framebuffer, &scene, &options, args.svg_style)?;
+        }
+        return Ok(());
     }
-}
-
-fn update_scene(scene: &mut Scene, t: f32) {
-    let tau = 2.0 * PI;
-    let t14 = t + 1.0 * tau / 4.0;
-    let t24 = t + 2.0 * tau / 4.0;
-    let t34 = t + 3.0 * tau / 4.0;
-
-    let rad = 2.0;
-
-    //scene.camera.rotation = Vec3::new(0.0, t * 0.1, 0.0);
 
-    scene.instances[0].translation = Vec4::new(rad * t.cos(), rad * t.sin(), 7.0, 0.0);
-    scene.instances[1].translation = Vec4::new(rad * t14.cos(), rad * t14.sin(), 7.0, 0.0);
-    scene.instances[2].translation = Vec4::new(rad * t24.cos(), rad * t24.sin(), 7.0, 0.0);
-    scene.instances[3].translation = Vec4::new(rad * t34.cos(), rad * t34.sin(), 7.0, 0.0);
-
-    scene.instances[1].scaling = Vec3::new(t.sin().abs(), t14.sin().abs(), t24.sin().abs());
-    scene.instances[2].scaling = Vec3::new(t.sin().abs(), t14.sin().abs(), t24.sin().abs());
+    if let Some(mode) = args.terminal {
+        return run_terminal(mode, &mut framebuffer, &mut scene, &mut options);
+    }
 
-    scene.instances[0].rotation = Vec3::new(t, t14, t24);
-    scene.instances[2].rotation = Vec3::new(t, t14, t24);
+    #[cfg(feature = "sdl")]
+    {
+        run_window(&mut framebuffer, &mut scene, &mut options, &args, stats_log)
+    }
+    #[cfg(not(feature = "sdl"))]
+    {
+        Err("built without the sdl feature, so only --output, --depth-output, --svg, --frames and --terminal work".into())
+    }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+/// Renders frames in a window until it is closed or Escape is pressed,
+/// handling the keys and mouse clicks that change how the scene is drawn.
+#[cfg(feature = "sdl")]
+fn run_window(
+    framebuffer: &mut Framebuffer,
+    scene: &mut Scene,
+    options: &mut RenderOptions,
+    args: &Args,
+    mut stats_log: Option<BufWriter<File>>,
+) -> Result<(), Box<dyn Error>> {
     let sdl = sdl2::init()?;
     let video_subsystem = sdl.video()?;
 
@@ -509,11 +342,26 @@ fn main() -> Result<(), Box<dyn Error>> {
         .build()?;
 
     let mut canvas = window.into_canvas().build()?;
-    let mut scene = build_scene();
-    let mut t = 0.0;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator.create_texture_streaming(
+        PixelFormatEnum::RGB24,
+        CANVAS_WIDTH,
+        CANVAS_HEIGHT,
+    )?;
+    let mut pixels = Vec::new();
+    // Where the last pick was in canvas space, and what was found there.
+    let mut pick_marker = None;
+    let mut pick_status: Vec<String> = Vec::new();
+    let mut clock = Clock::new();
+    // The time the scene was last posed at.
+    let mut posed_at = None;
+    let mut frame = 0;
+    let mut frame_start = Instant::now();
+    // How long the last frame took, and its stats.
+    let mut frame_time = Duration::ZERO;
+    let mut last_stats = RenderStats::default();
+    let mut recorder: Option<Recorder> = None;
     let mut event_pump = sdl.event_pump()?;
-    let mut draw = Draw::Pixels;
-    let mut cull_backfaces = Switch::On;
     'main_loop: loop {
         for event in event_pump.poll_iter() {
             match event {
@@ -528,39 +376,277 @@ fn main() -> Result<(), Box<dyn Error>> {
                     keycode: Some(Keycode::D),
                     ..
                 } => {
-                    draw = Draw::Depths;
+                    options.draw = Draw::Depths;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::R),
+                    ..
+                } => {
+                    options.auto_range_depths = options.auto_range_depths.toggle();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F),
+                    ..
+                } => {
+                    options.false_color_depths = options.false_color_depths.toggle();
                 }
                 Event::KeyDown {
                     keycode: Some(Keycode::P),
                     ..
                 } => {
-                    draw = Draw::Pixels;
+                    options.draw = Draw::Pixels;
                 }
                 Event::KeyDown {
                     keycode: Some(Keycode::W),
                     ..
                 } => {
-                    draw = Draw::Wireframe;
+                    options.draw = Draw::Wireframe;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::O),
+                    ..
+                } => {
+                    options.draw = Draw::Overlay;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::N),
+                    ..
+                } => {
+                    options.draw = Draw::Normals;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::G),
+                    ..
+                } => {
+                    options.draw = Draw::TriangleIds;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::K),
+                    ..
+                } => {
+                    options.draw = Draw::InstanceIds;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::B),
+                    ..
+                } => {
+                    options.draw = Draw::Barycentrics;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::V),
+                    ..
+                } => {
+                    options.draw = Draw::Overdraw;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Y),
+                    ..
+                } => {
+                    options.draw = Draw::RayTraced;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::H),
+                    ..
+                } => {
+                    options.depth_test_lines = options.depth_test_lines.toggle();
                 }
                 Event::KeyDown {
                     keycode: Some(Keycode::C),
                     ..
                 } => {
-                    cull_backfaces = cull_backfaces.toggle();
+                    options.cull_backfaces = options.cull_backfaces.toggle();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => {
+                    let samples = framebuffer.samples();
+                    let i = SAMPLE_COUNTS.iter().position(|&n| n == samples);
+                    let i = i.map_or(0, |i| (i + 1) % SAMPLE_COUNTS.len());
+                    framebuffer.set_samples(SAMPLE_COUNTS[i])?;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::T),
+                    ..
+                } => {
+                    // Cycles the material of the picked instance.
+                    let count = scene.materials.len().max(1);
+                    let picked = options.highlight.and_then(|i| scene.instances.get_mut(i));
+                    if let Some(instance) = picked {
+                        instance.material_index = (instance.material_index + 1) % count;
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::I),
+                    ..
+                } => {
+                    let capacity = match framebuffer.abuffer_capacity() {
+                        Some(_) => None,
+                        None => Some(args.abuffer.unwrap_or(DEFAULT_ABUFFER_CAPACITY)),
+                    };
+                    framebuffer.set_abuffer(capacity);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::S),
+                    ..
+                } => {
+                    options.hud = options.hud.toggle();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::X),
+                    ..
+                } => {
+                    options.overlay = options.overlay.toggle();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::L),
+                    ..
+                } => {
+                    options.line_mode = match options.line_mode {
+                        LineMode::Aliased => LineMode::AntiAliased,
+                        LineMode::AntiAliased => LineMode::Aliased,
+                    };
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => match recorder.take() {
+                    Some(r) => {
+                        let directory = r.directory().to_path_buf();
+                        let count = r.finish()?;
+                        println!("recorded {count} frames to {}", directory.display());
+                    }
+                    None => {
+                        let r = Recorder::new(&args.recordings, CANVAS_WIDTH, CANVAS_HEIGHT)?;
+                        println!("recording to {}", r.directory().display());
+                        recorder = Some(r);
+                    }
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::Space),
+                    ..
+                } => {
+                    clock.toggle_pause();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Period),
+                    ..
+                } => {
+                    clock.step(true);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Comma),
+                    ..
+                } => {
+                    clock.step(false);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Equals),
+                    ..
+                } => {
+                    clock.speed_up(2.0);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Minus),
+                    ..
+                } => {
+                    clock.speed_up(0.5);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => {
+                    clock.reverse();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Home),
+                    ..
+                } => {
+                    clock.rewind();
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    pick_marker = Some((x, y));
+                    let (x, y) = (x * args.supersample as i32, y * args.supersample as i32);
+                    let picked = pick(framebuffer, &scene.camera, x, y);
+                    pick_status = match &picked {
+                        Some(p) => vec![
+                            format!("pick: instance {}, triangle {}", p.instance, p.triangle),
+                            format!(
+                                "at ({:.2}, {:.2}, {:.2})",
+                                p.point[0], p.point[1], p.point[2]
+                            ),
+                        ],
+                        None => vec!["pick: nothing".to_string()],
+                    };
+                    options.highlight = picked.map(|p| p.instance);
+
+                    let (x, y) = framebuffer.canvas_to_plane(x as f32, y as f32);
+                    let ray = camera_ray(framebuffer, &scene.camera, x, y);
+                    match ray.and_then(|ray| scene.raycast(ray.origin, ray.direction)) {
+                        Some(hit) => {
+                            let [u, v, w] = hit.barycentrics;
+                            pick_status.push(format!(
+                                "ray: instance {}, triangle {}",
+                                hit.instance, hit.triangle
+                            ));
+                            pick_status.push(format!(
+                                "t = {:.2}, weights {u:.2} {v:.2} {w:.2}",
+                                hit.distance
+                            ));
+                        }
+                        None => pick_status.push("ray: nothing".to_string()),
+                    }
                 }
                 _ => {}
             }
         }
 
-        update_scene(&mut scene, t);
+        clock.tick();
+        if posed_at != Some(clock.time()) {
+            scene.animate(clock.time());
+            posed_at = Some(clock.time());
+        }
 
-        canvas.set_draw_color(Color::RGB(0x00, 0x00, 0x00));
-        canvas.clear();
-        render_scene(&mut canvas, &scene, draw, cull_backfaces)?;
+        let mut image = render_image(framebuffer, scene, options)?;
+        // Frames are recorded without the HUD, each shown for as long as the
+        // frame before it took.
+        if let Some(r) = &mut recorder {
+            r.add_frame(&image, frame_time)?;
+        }
+        if options.hud == Switch::On {
+            let mut status = vec![format!("clock: {clock}")];
+            status.extend_from_slice(&pick_status);
+            hud::draw_hud(&mut image, &last_stats, frame_time, options, &status);
+        }
+        let present_start = Instant::now();
+        present(&mut canvas, &mut texture, &mut pixels, &image)?;
+        if let (Switch::On, Some((x, y))) = (options.overlay, pick_marker) {
+            draw_crosshair(&mut canvas, x, y, ColorF32::YELLOW)?;
+        }
         canvas.present();
+        framebuffer.stats_mut().present_time += present_start.elapsed();
 
-        t += 0.005;
+        frame_time = frame_start.elapsed();
+        frame_start += frame_time;
+        last_stats = *framebuffer.stats();
+        if let Some(log) = &mut stats_log {
+            last_stats.write_csv_row(log, frame, frame_time)?;
+        }
+        frame += 1;
     }
 
+    if let Some(r) = recorder {
+        let directory = r.directory().to_path_buf();
+        let count = r.finish()?;
+        println!("recorded {count} frames to {}", directory.display());
+    }
+    if let Some(log) = &mut stats_log {
+        log.flush()?;
+    }
     Ok(())
 }
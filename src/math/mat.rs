@@ -4,18 +4,77 @@ use std::ops::Mul;
 
 use crate::math::vec::*;
 
-/// A 3x3 column major matrix.
-#[derive(Copy, Clone, Debug)]
-pub struct Mat3(pub [[f32; 3]; 3]);
-
-/// A 3x4 column major matrix.
-#[derive(Copy, Clone, Debug)]
-pub struct Mat3x4(pub [[f32; 3]; 4]);
-
 /// A 4x4 column major matrix.
 #[derive(Copy, Clone, Debug)]
 pub struct Mat4(pub [[f32; 4]; 4]);
 
+impl Mat4 {
+    /// The matrix that leaves every vector unchanged.
+    pub const IDENTITY: Mat4 = Mat4([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+
+    /// Computes the matrix that transforms normals consistently with the
+    /// directions this matrix transforms: the inverse transpose of its upper
+    /// left 3x3 block. Transformed normals need to be normalized again unless
+    /// the block is a rotation.
+    pub fn normal_matrix(&self) -> Mat4 {
+        let [a0, a1, a2] = [0, 1, 2].map(|c| {
+            let [x, y, z, _] = self.0[c];
+            Vec4::new(x, y, z, 0.0)
+        });
+        // The columns of the inverse transpose are the cross products of the
+        // other two columns, divided by the determinant.
+        let det = a0.dot(a1.cross(a2));
+        let [c0, c1, c2] = [a1.cross(a2), a2.cross(a0), a0.cross(a1)].map(|c| (c / det).0);
+        Mat4([c0, c1, c2, [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    /// Inverts the matrix by Gauss-Jordan elimination with partial pivoting,
+    /// returning `None` when it is singular.
+    pub fn inverse(&self) -> Option<Mat4> {
+        // Work on rows, each holding a row of this matrix followed by a row of
+        // the identity, which ends up holding the inverse.
+        let mut rows = [[0.0; 8]; 4];
+        for (r, row) in rows.iter_mut().enumerate() {
+            for (c, v) in row[..4].iter_mut().enumerate() {
+                *v = self.0[c][r];
+            }
+            row[4 + r] = 1.0;
+        }
+
+        for c in 0..4 {
+            let pivot = (c..4).max_by(|&i, &j| rows[i][c].abs().total_cmp(&rows[j][c].abs()))?;
+            if rows[pivot][c] == 0.0 {
+                return None;
+            }
+            rows.swap(c, pivot);
+            let scale = 1.0 / rows[c][c];
+            for v in rows[c].iter_mut() {
+                *v *= scale;
+            }
+            let pivot_row = rows[c];
+            for (_, row) in rows.iter_mut().enumerate().filter(|&(r, _)| r != c) {
+                let factor = row[c];
+                for (v, p) in row.iter_mut().zip(pivot_row) {
+                    *v -= factor * p;
+                }
+            }
+        }
+
+        let mut m = [[0.0; 4]; 4];
+        for (r, row) in rows.iter().enumerate() {
+            for (column, &v) in m.iter_mut().zip(&row[4..]) {
+                column[r] = v;
+            }
+        }
+        Some(Mat4(m))
+    }
+}
+
 /// Matrix multiplication.
 macro_rules! mul {
     ($m1:expr, $m2:expr, $n_rows:expr, $n_cols:expr, $n_inner:expr, $ty:ident) => {{
@@ -41,50 +100,108 @@ macro_rules! mul {
     }};
 }
 
-impl Mul<Mat3x4> for Mat3 {
-    type Output = Mat3x4;
+impl Mul for Mat4 {
+    type Output = Mat4;
 
-    fn mul(self, rhs: Mat3x4) -> Mat3x4 {
-        mul!(self, rhs, 3, 4, 3, Mat3x4)
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        mul!(self, rhs, 4, 4, 4, Mat4)
     }
 }
 
-impl Mul<Vec3> for Mat3 {
-    type Output = Vec3;
+impl Mul<Vec4> for Mat4 {
+    type Output = Vec4;
 
-    fn mul(self, rhs: Vec3) -> Vec3 {
-        mul!(self, rhs, 3, 3, Vec3)
+    fn mul(self, rhs: Vec4) -> Vec4 {
+        mul!(self, rhs, 4, 4, Vec4)
     }
 }
 
-impl Mul<Mat4> for Mat3x4 {
-    type Output = Mat3x4;
-
-    fn mul(self, rhs: Mat4) -> Mat3x4 {
-        mul!(self, rhs, 3, 4, 4, Mat3x4)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::transform::*;
+    use crate::test_util::Random;
+
+    fn assert_near(a: &Mat4, b: &Mat4, epsilon: f32) {
+        for c in 0..4 {
+            for r in 0..4 {
+                let (x, y) = (a.0[c][r], b.0[c][r]);
+                assert!(
+                    (x - y).abs() <= epsilon,
+                    "{a:?} differs from {b:?} at {c}, {r}"
+                );
+            }
+        }
     }
-}
-
-impl Mul<Vec4> for Mat3x4 {
-    type Output = Vec3;
 
-    fn mul(self, rhs: Vec4) -> Vec3 {
-        mul!(self, rhs, 3, 4, Vec3)
+    #[test]
+    fn inverts_transforms() {
+        let t = Vec4::new(3.0, -2.0, 5.0, 0.0);
+        let m =
+            translation(t) * rotation_y(0.7) * rotation_x(-1.2) * scaling(Vec3::new(2.0, 0.5, 4.0));
+        let expected = scaling(Vec3::new(0.5, 2.0, 0.25))
+            * rotation_x(1.2)
+            * rotation_y(-0.7)
+            * translation(-t);
+        let inverse = m.inverse().unwrap();
+        assert_near(&inverse, &expected, 1.0e-5);
+        assert_near(&(m * inverse), &Mat4::IDENTITY, 1.0e-5);
+        assert_near(&Mat4::IDENTITY.inverse().unwrap(), &Mat4::IDENTITY, 0.0);
     }
-}
 
-impl Mul for Mat4 {
-    type Output = Mat4;
+    #[test]
+    fn inverts_matrices_with_zeros_on_the_diagonal() {
+        // Projections have a zero in their last row and column, so
+        // eliminating them needs rows swapped.
+        let m = perspective_projection(1.0, 1.0, 1.0, 0.1, 100.0);
+        let inverse = m.inverse().unwrap();
+        assert_near(&(m * inverse), &Mat4::IDENTITY, 1.0e-5);
+        assert_near(&(inverse * m), &Mat4::IDENTITY, 1.0e-5);
+
+        let swap = Mat4([
+            [0.0, 1.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+            [0.0, 0.0, 1.0, 0.0],
+        ]);
+        assert_near(&swap.inverse().unwrap(), &swap, 0.0);
+    }
 
-    fn mul(self, rhs: Mat4) -> Mat4 {
-        mul!(self, rhs, 4, 4, 4, Mat4)
+    #[test]
+    fn inverts_random_matrices() {
+        let mut random = Random::new(4);
+        for _ in 0..100 {
+            // Adding to the diagonal keeps the matrices far from singular.
+            let mut m = Mat4([[0.0; 4]; 4]);
+            for c in 0..4 {
+                for r in 0..4 {
+                    m.0[c][r] = random.next() + if c == r { 4.0 } else { 0.0 };
+                }
+            }
+            let inverse = m.inverse().unwrap();
+            assert_near(&(m * inverse), &Mat4::IDENTITY, 1.0e-5);
+            assert_near(&(inverse * m), &Mat4::IDENTITY, 1.0e-5);
+        }
     }
-}
 
-impl Mul<Vec4> for Mat4 {
-    type Output = Vec4;
+    #[test]
+    fn rejects_singular_matrices() {
+        assert!(Mat4([[0.0; 4]; 4]).inverse().is_none());
+        assert!(scaling(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+        let repeated = Mat4([
+            [1.0, 2.0, 3.0, 0.0],
+            [1.0, 2.0, 3.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert!(repeated.inverse().is_none());
+    }
 
-    fn mul(self, rhs: Vec4) -> Vec4 {
-        mul!(self, rhs, 4, 4, Vec4)
+    #[test]
+    fn normal_matrices_undo_scaling() {
+        let rotation = rotation_z(0.4);
+        assert_near(&rotation.normal_matrix(), &rotation, 1.0e-6);
+        let normals = scaling(Vec3::new(2.0, 1.0, 1.0)).normal_matrix();
+        assert_near(&normals, &scaling(Vec3::new(0.5, 1.0, 1.0)), 1.0e-6);
     }
 }
@@ -1,4 +1,6 @@
+//! Vectors, matrices and quaternions for 3D transforms.
+
 pub mod mat;
-pub mod plane;
+pub mod quat;
 pub mod transform;
 pub mod vec;
@@ -0,0 +1,126 @@
+//! Quaternion type implementations.
+
+use std::ops::Mul;
+
+use crate::math::mat::*;
+use crate::math::vec::*;
+
+/// Rotations this close to each other are interpolated linearly, where
+/// spherical interpolation would divide by almost zero.
+const SLERP_EPSILON: f32 = 1.0e-4;
+
+/// A quaternion with its vector part first, used for rotations.
+#[derive(Copy, Clone, Debug)]
+pub struct Quat(pub [f32; 4]);
+
+impl Quat {
+    /// The rotation that leaves every direction unchanged.
+    pub const IDENTITY: Quat = Quat([0.0, 0.0, 0.0, 1.0]);
+
+    /// Creates a quaternion from its vector part and then its scalar part.
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Quat([x, y, z, w])
+    }
+
+    /// Creates the rotation by `angle` radians about a coordinate axis.
+    fn about_axis(axis: usize, angle: f32) -> Self {
+        let (s, c) = (angle / 2.0).sin_cos();
+        let mut q = [0.0, 0.0, 0.0, c];
+        q[axis] = s;
+        Quat(q)
+    }
+
+    /// Creates the rotation by the x, y, and z angles in radians in a given
+    /// vector, applying `rotation_x`, `rotation_y` and `rotation_z` in that
+    /// order.
+    pub fn from_euler(r: Vec3) -> Self {
+        // `rotation_x` and `rotation_y` turn the other way to `rotation_z`.
+        let q_x = Self::about_axis(0, -r[0]);
+        let q_y = Self::about_axis(1, -r[1]);
+        let q_z = Self::about_axis(2, r[2]);
+        q_z * q_y * q_x
+    }
+
+    /// The sum of the products of the components.
+    pub fn dot(self, rhs: Quat) -> f32 {
+        self.0.iter().zip(rhs.0).map(|(a, b)| a * b).sum()
+    }
+
+    /// Scales the quaternion to unit length.
+    pub fn normalize(self) -> Self {
+        let magnitude = self.dot(self).sqrt();
+        Quat(self.0.map(|v| v / magnitude))
+    }
+
+    /// Interpolates spherically from this rotation to another along the
+    /// shorter arc between them, at a constant angular speed. Values of `t`
+    /// outside `0..=1` continue along the same arc.
+    pub fn slerp(self, to: Quat, t: f32) -> Self {
+        // A quaternion and its negation are the same rotation, and the one
+        // nearer to this one is at the other end of the shorter arc.
+        let mut cos = self.dot(to);
+        let to = if cos < 0.0 {
+            cos = -cos;
+            Quat(to.0.map(|v| -v))
+        } else {
+            to
+        };
+
+        let (a, b) = if 1.0 - cos < SLERP_EPSILON {
+            (1.0 - t, t)
+        } else {
+            let angle = cos.min(1.0).acos();
+            let sin = angle.sin();
+            (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+        };
+        let mut q = [0.0; 4];
+        for (i, v) in q.iter_mut().enumerate() {
+            *v = a * self.0[i] + b * to.0[i];
+        }
+        Quat(q).normalize()
+    }
+
+    /// Creates the rotation matrix for the quaternion, which must be
+    /// normalized.
+    pub fn to_mat4(self) -> Mat4 {
+        let [x, y, z, w] = self.0;
+        Mat4([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y + w * z),
+                2.0 * (x * z - w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y - w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z + w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z + w * y),
+                2.0 * (y * z - w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+/// Quaternion multiplication, which composes rotations so that the right
+/// hand side is applied first.
+impl Mul for Quat {
+    type Output = Quat;
+
+    fn mul(self, rhs: Quat) -> Quat {
+        let [x1, y1, z1, w1] = self.0;
+        let [x2, y2, z2, w2] = rhs.0;
+        Quat::new(
+            w1 * x2 + x1 * w2 + y1 * z2 - z1 * y2,
+            w1 * y2 - x1 * z2 + y1 * w2 + z1 * x2,
+            w1 * z2 + x1 * y2 - y1 * x2 + z1 * w2,
+            w1 * w2 - x1 * x2 - y1 * y2 - z1 * z2,
+        )
+    }
+}
@@ -11,16 +11,20 @@ pub struct Vec3(pub [f32; 3]);
 pub struct Vec4(pub [f32; 4]);
 
 impl Vec3 {
+    /// Creates a vector from its components.
     pub fn new(x: f32, y: f32, z: f32) -> Self {
         Vec3([x, y, z])
     }
 }
 
 impl Vec4 {
+    /// Creates a vector from its components. Points have a `w` of one and
+    /// directions a `w` of zero.
     pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
         Vec4([x, y, z, w])
     }
 
+    /// The cross product of the x, y and z components, as a direction.
     pub fn cross(self, rhs: Vec4) -> Vec4 {
         let (a1, a2, a3) = (self[0], self[1], self[2]);
         let (b1, b2, b3) = (rhs[0], rhs[1], rhs[2]);
@@ -32,14 +36,17 @@ impl Vec4 {
         )
     }
 
+    /// The dot product of the x, y and z components.
     pub fn dot(self, rhs: Vec4) -> f32 {
         self[0] * rhs[0] + self[1] * rhs[1] + self[2] * rhs[2]
     }
 
+    /// The length of the x, y and z components.
     pub fn magnitude(self) -> f32 {
         self.dot(self).sqrt()
     }
 
+    /// Scales the vector so that its x, y and z components have unit length.
     pub fn normalize(self) -> Self {
         self / self.magnitude()
     }
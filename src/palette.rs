@@ -0,0 +1,254 @@
+//! Reduction of images to a palette of at most 256 colors, as GIF frames need.
+
+/// Colors are counted in a histogram at this many bits per channel, which
+/// groups nearly equal colors and keeps the histogram small.
+const HISTOGRAM_BITS: u32 = 5;
+
+const HISTOGRAM_SIZE: usize = 1 << (3 * HISTOGRAM_BITS);
+
+/// Marks histogram cells whose nearest palette color is not known yet.
+const UNKNOWN: u16 = u16::MAX;
+
+/// The colors of an image that fall in one histogram cell.
+#[derive(Clone, Copy, Default)]
+struct Cell {
+    count: u32,
+    /// The sums of each channel over the colors.
+    sums: [u64; 3],
+}
+
+/// A set of colors and a lookup from colors to their nearest entries.
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+    /// The index of the palette color nearest to each histogram cell, filled
+    /// in as cells are looked up.
+    nearest: Vec<u16>,
+}
+
+impl Palette {
+    /// Chooses up to `max_colors` colors for an image given as RGB bytes by
+    /// median cut. The image's colors are split into boxes, repeatedly
+    /// halving the box with the widest range of a channel at the median of
+    /// that channel, and each box's mean color joins the palette.
+    pub fn median_cut(rgb: &[u8], max_colors: usize) -> Self {
+        let mut histogram = vec![Cell::default(); HISTOGRAM_SIZE];
+        for pixel in rgb.chunks_exact(3) {
+            let cell = &mut histogram[cell_index(pixel)];
+            cell.count += 1;
+            for (sum, &v) in cell.sums.iter_mut().zip(pixel) {
+                *sum += v as u64;
+            }
+        }
+        let cells: Vec<usize> = (0..HISTOGRAM_SIZE)
+            .filter(|&i| histogram[i].count > 0)
+            .collect();
+
+        let mut boxes = vec![cells];
+        while boxes.len() < max_colors {
+            // Split the box with the widest channel range, if any box has
+            // more than one cell left.
+            let widest = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, cells)| cells.len() > 1)
+                .map(|(i, cells)| (i, widest_channel(cells)))
+                .max_by_key(|&(_, (_, range))| range);
+            let Some((i, (channel, _))) = widest else {
+                break;
+            };
+
+            let cells = &mut boxes[i];
+            cells.sort_by_key(|&cell| cell_channel(cell, channel));
+            let total: u32 = cells.iter().map(|&cell| histogram[cell].count).sum();
+            // Split after the cell where half of the colors have been
+            // counted, leaving at least one cell on each side.
+            let mut counted = 0;
+            let mut median = 1;
+            for (j, &cell) in cells.iter().enumerate() {
+                counted += histogram[cell].count;
+                if 2 * counted >= total {
+                    median = (j + 1).clamp(1, cells.len() - 1);
+                    break;
+                }
+            }
+            let upper = cells.split_off(median);
+            boxes.push(upper);
+        }
+
+        let colors = boxes
+            .iter()
+            .filter(|cells| !cells.is_empty())
+            .map(|cells| {
+                let mut count = 0;
+                let mut sums = [0; 3];
+                for &cell in cells {
+                    count += histogram[cell].count as u64;
+                    for (sum, v) in sums.iter_mut().zip(histogram[cell].sums) {
+                        *sum += v;
+                    }
+                }
+                sums.map(|sum| (sum / count) as u8)
+            })
+            .collect();
+        Palette {
+            colors,
+            nearest: vec![UNKNOWN; HISTOGRAM_SIZE],
+        }
+    }
+
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+
+    /// Replaces `indices` with palette indices for an image given as RGB
+    /// bytes in rows of `width` pixels. Each pixel's difference from its
+    /// palette color is spread over its unvisited neighbors by Floyd-Steinberg
+    /// dithering, which trades banding in gradients for fine noise.
+    pub fn map(&mut self, rgb: &[u8], width: usize, indices: &mut Vec<u8>) {
+        indices.clear();
+        // The errors carried to this row and the next, with a pixel of
+        // padding at each end.
+        let mut errors = vec![[0.0f32; 3]; width + 2];
+        let mut next_errors = vec![[0.0f32; 3]; width + 2];
+        for row in rgb.chunks_exact(3 * width) {
+            for (x, pixel) in row.chunks_exact(3).enumerate() {
+                let wanted =
+                    [0, 1, 2].map(|c| (pixel[c] as f32 + errors[x + 1][c]).clamp(0.0, 255.0));
+                let index = self.nearest(wanted.map(|v| v.round() as u8));
+                indices.push(index);
+                let color = self.colors[index as usize];
+                for c in 0..3 {
+                    let error = wanted[c] - color[c] as f32;
+                    errors[x + 2][c] += error * 7.0 / 16.0;
+                    next_errors[x][c] += error * 3.0 / 16.0;
+                    next_errors[x + 1][c] += error * 5.0 / 16.0;
+                    next_errors[x + 2][c] += error * 1.0 / 16.0;
+                }
+            }
+            std::mem::swap(&mut errors, &mut next_errors);
+            next_errors.fill([0.0; 3]);
+        }
+    }
+
+    /// Finds the palette index nearest to a color, looking it up by the
+    /// histogram cell the color falls in.
+    fn nearest(&mut self, color: [u8; 3]) -> u8 {
+        let cell = cell_index(&color);
+        if self.nearest[cell] == UNKNOWN {
+            self.nearest[cell] = self.find_nearest(cell_center(cell));
+        }
+        self.nearest[cell] as u8
+    }
+
+    fn find_nearest(&self, color: [u8; 3]) -> u16 {
+        let distance = |c: &[u8; 3]| -> u32 {
+            c.iter()
+                .zip(color)
+                .map(|(&a, b)| (a as i32 - b as i32).pow(2) as u32)
+                .sum()
+        };
+        (0..self.colors.len())
+            .min_by_key(|&i| distance(&self.colors[i]))
+            .unwrap_or(0) as u16
+    }
+}
+
+fn cell_index(pixel: &[u8]) -> usize {
+    let shift = 8 - HISTOGRAM_BITS;
+    pixel[..3].iter().fold(0, |index, &v| {
+        index << HISTOGRAM_BITS | (v >> shift) as usize
+    })
+}
+
+/// The value of one channel of a cell at the histogram's precision.
+fn cell_channel(cell: usize, channel: usize) -> usize {
+    let mask = (1 << HISTOGRAM_BITS) - 1;
+    cell >> (HISTOGRAM_BITS as usize * (2 - channel)) & mask
+}
+
+/// The color at the middle of a cell.
+fn cell_center(cell: usize) -> [u8; 3] {
+    let shift = 8 - HISTOGRAM_BITS;
+    [0, 1, 2].map(|channel| ((cell_channel(cell, channel) << shift) + (1 << (shift - 1))) as u8)
+}
+
+/// Finds the channel whose values vary the most over some cells, and how
+/// much they vary.
+fn widest_channel(cells: &[usize]) -> (usize, usize) {
+    (0..3)
+        .map(|channel| {
+            let values = cells.iter().map(|&cell| cell_channel(cell, channel));
+            let min = values.clone().min().unwrap_or(0);
+            let max = values.max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap_or((0, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_few_colors_exactly() {
+        let colors = [[0, 0, 0], [255, 255, 255], [255, 0, 0], [0, 128, 255]];
+        let rgb: Vec<u8> = (0..40).flat_map(|i| colors[i * 7 % 4]).collect();
+        let mut palette = Palette::median_cut(&rgb, 256);
+        assert_eq!(palette.colors().len(), colors.len());
+        for color in colors {
+            assert!(palette.colors().contains(&color), "{color:?}");
+        }
+
+        // Exact colors leave no error to dither.
+        let mut indices = Vec::new();
+        palette.map(&rgb, 8, &mut indices);
+        let mapped: Vec<u8> = indices
+            .iter()
+            .flat_map(|&i| palette.colors()[i as usize])
+            .collect();
+        assert_eq!(mapped, rgb);
+    }
+
+    #[test]
+    fn limits_the_number_of_colors() {
+        let rgb: Vec<u8> = (0..64 * 64)
+            .flat_map(|i| [(i % 64 * 4) as u8, (i / 64 * 4) as u8, 128])
+            .collect();
+        for max_colors in [1, 2, 16, 256] {
+            let mut palette = Palette::median_cut(&rgb, max_colors);
+            assert_eq!(palette.colors().len(), max_colors);
+            let mut indices = Vec::new();
+            palette.map(&rgb, 64, &mut indices);
+            assert_eq!(indices.len(), 64 * 64);
+            assert!(indices.iter().all(|&i| (i as usize) < max_colors));
+        }
+    }
+
+    #[test]
+    fn dithering_keeps_the_average_color() {
+        // A gray between two palette colors comes out as a mix of both.
+        let rgb: Vec<u8> = (0..32 * 32)
+            .flat_map(|i| [(i % 2 * 255) as u8; 3])
+            .collect();
+        let mut palette = Palette::median_cut(&rgb, 2);
+        let gray = [96u8; 3].repeat(32 * 32);
+        let mut indices = Vec::new();
+        palette.map(&gray, 32, &mut indices);
+        let mean = indices
+            .iter()
+            .map(|&i| palette.colors()[i as usize][0] as f32)
+            .sum::<f32>()
+            / indices.len() as f32;
+        assert!((mean - 96.0).abs() < 4.0, "{mean}");
+    }
+
+    #[test]
+    fn handles_empty_images() {
+        let mut palette = Palette::median_cut(&[], 256);
+        assert!(palette.colors().is_empty());
+        let mut indices = vec![1];
+        palette.map(&[], 4, &mut indices);
+        assert!(indices.is_empty());
+    }
+}
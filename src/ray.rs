@@ -0,0 +1,66 @@
+//! Rays and their intersections with triangles.
+
+use crate::math::mat::Mat4;
+use crate::math::vec::Vec4;
+
+/// Triangles whose planes are this close to parallel to a ray are not hit.
+const PARALLEL_EPSILON: f32 = 1.0e-7;
+
+/// A half line starting at `origin`. Points along it are `origin + t *
+/// direction` for `t >= 0`, and `direction` need not be normalized.
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    /// The point the ray starts from.
+    pub origin: Vec4,
+    /// The direction of the ray, whose length is the distance covered per
+    /// unit of the ray parameter.
+    pub direction: Vec4,
+}
+
+impl Ray {
+    /// Creates a ray from where it starts and its direction.
+    pub fn new(origin: Vec4, direction: Vec4) -> Self {
+        Self { origin, direction }
+    }
+
+    /// The point at parameter `t` along the ray.
+    pub fn at(&self, t: f32) -> Vec4 {
+        self.origin + self.direction * t
+    }
+
+    /// Transforms the ray by a matrix. The parameter of each point along the
+    /// ray is unchanged, so distances found in the transformed space compare
+    /// directly with those found in the original space.
+    pub fn transform(&self, m: &Mat4) -> Self {
+        Self::new(*m * self.origin, *m * self.direction)
+    }
+}
+
+/// Intersects a ray with a triangle using the Möller–Trumbore algorithm.
+/// Returns the ray parameter of the hit and the barycentric weights of the
+/// triangle's vertices there, or `None` when the ray misses. Both sides of
+/// the triangle can be hit.
+pub fn intersect_triangle(ray: &Ray, v: [Vec4; 3]) -> Option<(f32, [f32; 3])> {
+    let e1 = v[1] - v[0];
+    let e2 = v[2] - v[0];
+    let p = ray.direction.cross(e2);
+    let det = e1.dot(p);
+    if det.abs() < PARALLEL_EPSILON {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let s = ray.origin - v[0];
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(e1);
+    let w = ray.direction.dot(q) * inv_det;
+    if w < 0.0 || u + w > 1.0 {
+        return None;
+    }
+
+    let t = e2.dot(q) * inv_det;
+    (t >= 0.0).then_some((t, [1.0 - u - w, u, w]))
+}
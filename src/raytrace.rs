@@ -0,0 +1,134 @@
+//! A ray tracer that renders the same scenes as the rasterizer, adding
+//! lighting, shadows and reflections. It is slow, but serves as a reference
+//! for rasterized frames and for high quality stills.
+
+use std::time::Instant;
+
+use crate::gfx::{BlendMode, ColorF32, Framebuffer, PrimitiveId};
+use crate::ray::Ray;
+use crate::render::camera_ray;
+use crate::scene::{RayHit, Scene};
+
+/// How many times a ray may be reflected or pass through a surface before
+/// tracing stops.
+const MAX_RAY_DEPTH: u32 = 5;
+
+/// How far rays leaving a surface start from it, so that they do not hit the
+/// surface they leave.
+const SURFACE_OFFSET: f32 = 1.0e-3;
+
+/// The color of rays that hit nothing.
+const BACKGROUND: ColorF32 = ColorF32::BLACK;
+
+/// Renders a scene by tracing a ray through every sample of the framebuffer.
+/// As when rasterizing, samples that see an opaque surface first take its
+/// depth and triangle along with their color. The scene must be valid.
+pub(crate) fn render(framebuffer: &mut Framebuffer, scene: &Scene) {
+    let start = Instant::now();
+    let offsets = framebuffer.sample_offsets();
+    for y in 0..framebuffer.height() as i32 {
+        for x in 0..framebuffer.width() as i32 {
+            let Some(index) = framebuffer.index(x, y) else {
+                continue;
+            };
+            let (plane_x, plane_y) = framebuffer.canvas_to_plane(x as f32, y as f32);
+            for (s, &(dx, dy)) in offsets.iter().enumerate() {
+                let Some(ray) = camera_ray(framebuffer, &scene.camera, plane_x + dx, plane_y + dy)
+                else {
+                    return;
+                };
+                let Some(hit) = scene.raycast(ray.origin, ray.direction) else {
+                    framebuffer.set_color(index, s, BACKGROUND);
+                    continue;
+                };
+
+                framebuffer.set_color(index, s, shade(scene, &ray, &hit, 0));
+                framebuffer.stats_mut().pixels_shaded += 1;
+                let instance = &scene.instances[hit.instance];
+                if scene.materials[instance.material_index].blend == BlendMode::Opaque {
+                    // Camera rays reach a camera space depth of t at t.
+                    framebuffer.set_depth(index, s, 1.0 / hit.distance);
+                    let id = PrimitiveId {
+                        instance: hit.instance,
+                        triangle: hit.triangle,
+                    };
+                    framebuffer.set_id(index, s, id);
+                }
+            }
+        }
+    }
+    framebuffer.stats_mut().trace_time += start.elapsed();
+}
+
+/// Finds the color of the light arriving along a ray.
+fn trace(scene: &Scene, ray: &Ray, depth: u32) -> ColorF32 {
+    match scene.raycast(ray.origin, ray.direction) {
+        Some(hit) => shade(scene, ray, &hit, depth),
+        None => BACKGROUND,
+    }
+}
+
+/// Finds the color of the light leaving a surface a ray hit, back along the
+/// ray. Surfaces are lit diffusely, and every surface casts shadows, even
+/// transparent ones. Blended surfaces are combined with what lies behind them
+/// using their material's blend mode.
+fn shade(scene: &Scene, ray: &Ray, hit: &RayHit, depth: u32) -> ColorF32 {
+    let instance = &scene.instances[hit.instance];
+    let model = &scene.models[instance.model_index.expect("only models can be hit")];
+    let material = scene.materials[instance.material_index];
+    let triangle = &model.triangles[hit.triangle];
+
+    let color = triangle
+        .indices_color
+        .iter()
+        .zip(hit.barycentrics)
+        .fold(ColorF32::new_rgba(0.0, 0.0, 0.0, 0.0), |c, (&i, w)| {
+            c + model.colors[i] * w
+        });
+
+    // Face the normal towards the ray, so that both sides are lit alike.
+    let normal_matrix = instance.world.normal_matrix();
+    let mut normal = (normal_matrix * model.normals[hit.triangle]).normalize();
+    if normal.dot(ray.direction) > 0.0 {
+        normal = -normal;
+    }
+    let above = hit.point + normal * SURFACE_OFFSET;
+
+    let mut light = scene.ambient;
+    for l in scene.lights.iter() {
+        let to_light = l.position - hit.point;
+        let cos = normal.dot(to_light.normalize());
+        if cos <= 0.0 {
+            continue;
+        }
+        // The shadow ray reaches the light at a parameter of one.
+        let shadowed = scene
+            .raycast(above, to_light)
+            .is_some_and(|h| h.distance < 1.0);
+        if !shadowed {
+            light += l.intensity * cos;
+        }
+    }
+    let (r, g, b) = color.rgb();
+    let mut surface = ColorF32::new(r * light, g * light, b * light);
+
+    if depth >= MAX_RAY_DEPTH {
+        return surface;
+    }
+
+    if material.reflectivity > 0.0 {
+        let d = ray.direction;
+        let reflected = Ray::new(above, d - normal * (2.0 * d.dot(normal)));
+        let mirrored = trace(scene, &reflected, depth + 1);
+        surface = surface * (1.0 - material.reflectivity) + mirrored * material.reflectivity;
+    }
+
+    if material.blend != BlendMode::Opaque {
+        let below = hit.point - normal * SURFACE_OFFSET;
+        let behind = trace(scene, &Ray::new(below, ray.direction), depth + 1);
+        let alpha = color.alpha() * material.opacity;
+        return surface.with_alpha(alpha).blend_over(behind, material.blend);
+    }
+
+    surface
+}
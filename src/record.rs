@@ -0,0 +1,75 @@
+//! Recording of consecutive frames to numbered PNG files and an animated GIF.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::gif::GifWriter;
+use crate::image::Image;
+
+/// The shortest delay in hundredths of a second that GIF viewers honor.
+/// Shorter delays are commonly shown as a tenth of a second instead.
+const MIN_GIF_DELAY: f32 = 2.0;
+
+/// Writes the frames of one recording to a new directory.
+pub struct Recorder {
+    directory: PathBuf,
+    frames: u32,
+    gif: GifWriter<BufWriter<File>>,
+    /// Time in hundredths of a second that has passed but not been given to
+    /// a GIF frame's delay, which is a whole number of them.
+    pending_delay: f32,
+}
+
+impl Recorder {
+    /// Starts a recording of frames of a given size in the first numbered
+    /// directory under `base` that does not exist yet.
+    pub fn new<P: AsRef<Path>>(base: P, width: u32, height: u32) -> io::Result<Self> {
+        let directory = (0..=u32::MAX)
+            .map(|n| base.as_ref().join(format!("{n:03}")))
+            .find(|directory| !directory.exists())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "every numbered recording directory is taken",
+                )
+            })?;
+        fs::create_dir_all(&directory)?;
+        let gif = BufWriter::new(File::create(directory.join("animation.gif"))?);
+        Ok(Recorder {
+            gif: GifWriter::new(gif, width, height)?,
+            directory,
+            frames: 0,
+            pending_delay: 0.0,
+        })
+    }
+
+    /// The directory the frames are written to.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Writes a frame that is shown for a given time.
+    pub fn add_frame(&mut self, image: &Image, duration: Duration) -> io::Result<()> {
+        let path = self.directory.join(format!("frame_{:04}.png", self.frames));
+        image.write_png(path)?;
+
+        // Rounding errors are carried to later frames, so that the animation
+        // keeps time over many frames. Time added by the shortest delay is
+        // not, so that a run of quick frames does not shorten later ones.
+        self.pending_delay += duration.as_secs_f32() * 100.0;
+        let delay = self.pending_delay.round().max(MIN_GIF_DELAY);
+        self.pending_delay = (self.pending_delay - delay).max(-0.5);
+        self.gif
+            .write_frame(image, delay.min(u16::MAX as f32) as u16)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Finishes the recording, returning the number of frames written.
+    pub fn finish(self) -> io::Result<u32> {
+        self.gif.finish()?;
+        Ok(self.frames)
+    }
+}
@@ -0,0 +1,967 @@
+//! The rasterization pipeline, which projects the triangles of a scene and
+//! fills them into a framebuffer.
+
+use std::cmp::Ordering;
+use std::time::Instant;
+
+use crate::clip::*;
+use crate::error::RenderError;
+use crate::gfx::*;
+use crate::image::*;
+use crate::math::vec::*;
+use crate::ray::*;
+use crate::raytrace;
+use crate::scene::*;
+use crate::shader::*;
+
+/// A setting that is either on or off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Switch {
+    /// The setting is disabled.
+    Off,
+    /// The setting is enabled.
+    On,
+}
+
+impl Switch {
+    /// Returns the other setting.
+    pub fn toggle(self) -> Self {
+        match self {
+            Self::On => Self::Off,
+            Self::Off => Self::On,
+        }
+    }
+}
+
+/// What is drawn of a scene.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Draw {
+    /// Camera space depths, linearly mapped to brightness with nearer depths
+    /// brighter.
+    Depths,
+    /// Filled triangles shaded with their vertex colors and materials.
+    Pixels,
+    /// The edges of the triangles.
+    Wireframe,
+    /// Filled triangles with their edges drawn on top.
+    Overlay,
+    /// Camera space normals as colors.
+    Normals,
+    /// A distinct color per triangle.
+    TriangleIds,
+    /// A distinct color per instance.
+    InstanceIds,
+    /// Barycentric coordinates as colors.
+    Barycentrics,
+    /// The number of fragments rasterized at each pixel as a heatmap.
+    Overdraw,
+    /// The scene ray traced with lighting, shadows and reflections.
+    RayTraced,
+}
+
+/// Settings that control how a scene is rendered. The defaults are those
+/// the viewer starts with.
+pub struct RenderOptions {
+    /// What is drawn of the scene.
+    pub draw: Draw,
+    /// Whether triangles facing away from the camera are skipped.
+    pub cull_backfaces: Switch,
+    /// How wireframe edges are rasterized.
+    pub line_mode: LineMode,
+    /// Whether wireframe edges are hidden behind nearer faces.
+    pub depth_test_lines: Switch,
+    /// Whether the depth view maps the range of drawn depths rather than the
+    /// camera's near and far planes.
+    pub auto_range_depths: Switch,
+    /// Whether the depth view uses a false color palette instead of grays.
+    pub false_color_depths: Switch,
+    /// An instance whose visible edges are drawn highlighted over the frame.
+    pub highlight: Option<usize>,
+    /// Whether frame stats are drawn over the frame in the window.
+    pub hud: Switch,
+    /// Whether debug marks, such as where the last pick was, are drawn over
+    /// the presented frame in the window.
+    pub overlay: Switch,
+    /// The factor the framebuffer is larger than the canvas by, which
+    /// rendered frames are downsampled by.
+    pub supersample: u32,
+    /// The filter supersampled frames are downsampled with.
+    pub filter: Filter,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            draw: Draw::Pixels,
+            cull_backfaces: Switch::On,
+            line_mode: LineMode::AntiAliased,
+            depth_test_lines: Switch::On,
+            auto_range_depths: Switch::On,
+            false_color_depths: Switch::Off,
+            highlight: None,
+            hud: Switch::On,
+            overlay: Switch::On,
+            supersample: 1,
+            filter: Filter::Tent,
+        }
+    }
+}
+
+/// The surface found under a point of the canvas.
+pub struct Pick {
+    /// The index of the instance in `Scene::instances`.
+    pub instance: usize,
+    /// The index of the triangle in the instance's model.
+    pub triangle: usize,
+    /// The point on the triangle in world space.
+    pub point: Vec4,
+}
+
+/// A point of a projected triangle in plane space, carrying what is
+/// interpolated across the triangle.
+#[derive(Clone, Copy)]
+pub struct Fragment<V> {
+    /// The horizontal position in plane space.
+    pub x: f32,
+    /// The vertical position in plane space.
+    pub y: f32,
+    /// The reciprocal of the camera space depth, which is larger for nearer
+    /// fragments.
+    pub depth: f32,
+    /// The varyings divided by the camera space depth, which makes them vary
+    /// linearly in plane space like `depth` does.
+    pub varyings: V,
+}
+
+/// A triangle that has been clipped and projected to plane space.
+#[derive(Clone, Copy)]
+pub(crate) struct ProjectedTriangle<V> {
+    pub fragments: [Fragment<V>; 3],
+    pub blend: BlendMode,
+    pub id: PrimitiveId,
+}
+
+impl<V: Varyings> Fragment<V> {
+    fn slope_by_y(self, to: Self) -> Self {
+        let y_delta = to.y - self.y;
+        Self {
+            x: (to.x - self.x) / y_delta,
+            y: 1.0,
+            depth: (to.depth - self.depth) / y_delta,
+            varyings: to
+                .varyings
+                .add(self.varyings.scale(-1.0))
+                .scale(1.0 / y_delta),
+        }
+    }
+
+    fn slope_by_x(self, to: Self) -> Self {
+        let x_delta = to.x - self.x;
+        Self {
+            x: 1.0,
+            y: (to.y - self.y) / x_delta,
+            depth: (to.depth - self.depth) / x_delta,
+            varyings: to
+                .varyings
+                .add(self.varyings.scale(-1.0))
+                .scale(1.0 / x_delta),
+        }
+    }
+
+    /// Combines the fragments of a triangle using barycentric weights.
+    fn barycentric(p: &[Self; 3], w: [f32; 3]) -> Self {
+        let combine = |v: fn(&Self) -> f32| w[0] * v(&p[0]) + w[1] * v(&p[1]) + w[2] * v(&p[2]);
+        Self {
+            x: combine(|f| f.x),
+            y: combine(|f| f.y),
+            depth: combine(|f| f.depth),
+            varyings: p[0]
+                .varyings
+                .scale(w[0])
+                .add(p[1].varyings.scale(w[1]))
+                .add(p[2].varyings.scale(w[2])),
+        }
+    }
+
+    /// Prepares the fragment for a fragment shader, undoing the division of
+    /// the varyings by the camera space depth.
+    pub fn to_input(self) -> FragmentInput<V> {
+        FragmentInput {
+            depth: self.depth,
+            varyings: self.varyings.scale(1.0 / self.depth),
+        }
+    }
+}
+
+impl<V: Varyings> std::ops::AddAssign for Fragment<V> {
+    fn add_assign(&mut self, rhs: Self) {
+        self.x += rhs.x;
+        self.y += rhs.y;
+        self.depth += rhs.depth;
+        self.varyings = self.varyings.add(rhs.varyings);
+    }
+}
+
+impl<V> ProjectedTriangle<V> {
+    /// The average depth of the vertices, used to order blended triangles.
+    pub fn mean_depth(&self) -> f32 {
+        let [p0, p1, p2] = &self.fragments;
+        (p0.depth + p1.depth + p2.depth) / 3.0
+    }
+}
+
+/// Divides a clip space vertex by its w component and maps the result to plane
+/// space. The depth is the reciprocal of the camera space depth.
+fn projected_to_point<V: Varyings>(framebuffer: &Framebuffer, v: ClipVertex<V>) -> Fragment<V> {
+    let w = v.position[3];
+    let x = v.position[0] / w * (framebuffer.width() as f32) / 2.0;
+    let y = v.position[1] / w * (framebuffer.height() as f32) / 2.0;
+    let depth = 1.0 / w;
+    let varyings = v.varyings.scale(depth);
+    Fragment {
+        x,
+        y,
+        depth,
+        varyings,
+    }
+}
+
+fn i32_range(x: f32, y: f32) -> core::ops::Range<i32> {
+    (x as i32)..(y as i32)
+}
+
+fn i32_range_inclusive(x: f32, y: f32) -> core::ops::RangeInclusive<i32> {
+    (x as i32)..=(y as i32)
+}
+
+/// Polygon offset slope factor for depth-tested lines. The offset grows with
+/// how steeply the depth of the face an edge belongs to changes per pixel.
+const LINE_OFFSET_FACTOR: f32 = 1.0;
+
+/// Polygon offset constant for depth-tested lines, relative to the depth of
+/// the line.
+const LINE_OFFSET_UNITS: f32 = 1.0e-3;
+
+/// Shades a fragment with an optional fragment shader, counting the pixel as
+/// shaded. Returns `None` when the shader discards the fragment, and
+/// `Some(None)` when there is no shader and only the fragment's depth is
+/// written.
+fn shade_fragment<V, FS>(
+    framebuffer: &mut Framebuffer,
+    f: &Fragment<V>,
+    fs: Option<&FS>,
+) -> Option<Option<ColorF32>>
+where
+    V: Varyings,
+    FS: FragmentShader<V>,
+{
+    match fs {
+        Some(fs) => {
+            framebuffer.stats_mut().pixels_shaded += 1;
+            fs.shade(&f.to_input()).map(Some)
+        }
+        None => Some(None),
+    }
+}
+
+/// Writes a fragment that passed the depth test to a sample. Opaque fragments
+/// replace the sample's color, depth and ID, while blended fragments are
+/// combined with the sample's color, possibly deferred until the framebuffer
+/// is resolved, and leave its depth and ID untouched.
+fn write_sample(
+    framebuffer: &mut Framebuffer,
+    index: usize,
+    sample: usize,
+    depth: f32,
+    color: Option<ColorF32>,
+    blend: BlendMode,
+    id: PrimitiveId,
+) {
+    if blend == BlendMode::Opaque {
+        framebuffer.set_depth(index, sample, depth);
+        framebuffer.set_id(index, sample, id);
+        if let Some(c) = color {
+            framebuffer.set_color(index, sample, c);
+        }
+    } else if let Some(c) = color {
+        framebuffer.blend_deferred(index, sample, c, depth, blend);
+    }
+}
+
+fn draw_line_horizontal<V, FS>(
+    framebuffer: &mut Framebuffer,
+    f1: Fragment<V>,
+    f2: Fragment<V>,
+    y: i32,
+    fs: Option<&FS>,
+    blend: BlendMode,
+    id: PrimitiveId,
+) where
+    V: Varyings,
+    FS: FragmentShader<V>,
+{
+    let (f_left, f_right) = if f1.x > f2.x {
+        (f2, f1)
+    } else {
+        (f1, f2)
+    };
+    let mut f = f_left;
+    let f_slope = f_left.slope_by_x(f_right);
+    for x in i32_range_inclusive(f_left.x, f_right.x) {
+        let (canvas_x, canvas_y) = framebuffer.plane_to_canvas(x as f32, y as f32);
+        if let Some(index) = framebuffer.index(canvas_x as i32, canvas_y as i32) {
+            if f.depth > framebuffer.depth(index, 0) {
+                if let Some(color) = shade_fragment(framebuffer, &f, fs) {
+                    write_sample(framebuffer, index, 0, f.depth, color, blend, id);
+                }
+            }
+        }
+        f += f_slope;
+    }
+}
+
+/// Rasterizes a triangle, shading its fragments with `fs`. Without a fragment
+/// shader, only depths are written.
+pub(crate) fn fill_triangle<V, FS>(
+    framebuffer: &mut Framebuffer,
+    triangle: &ProjectedTriangle<V>,
+    fs: Option<&FS>,
+) where
+    V: Varyings,
+    FS: FragmentShader<V>,
+{
+    let start = Instant::now();
+    if framebuffer.samples() > 1 {
+        fill_triangle_multisample(framebuffer, triangle, fs);
+    } else {
+        fill_triangle_scanline(framebuffer, triangle, fs);
+    }
+    let stats = framebuffer.stats_mut();
+    stats.triangles_rasterized += 1;
+    stats.rasterize_time += start.elapsed();
+}
+
+/// Rasterizes a triangle into a framebuffer with one sample per pixel, a
+/// horizontal line at a time.
+fn fill_triangle_scanline<V, FS>(
+    framebuffer: &mut Framebuffer,
+    triangle: &ProjectedTriangle<V>,
+    fs: Option<&FS>,
+) where
+    V: Varyings,
+    FS: FragmentShader<V>,
+{
+    let mut p = triangle.fragments;
+    let (blend, id) = (triangle.blend, triangle.id);
+    p.sort_by(|p, q| p.y.total_cmp(&q.y));
+
+    let mut long = p[0];
+    let long_slope = p[0].slope_by_y(p[2]);
+
+    for i in 0..=1 {
+        let mut short = p[i];
+        let short_slope = p[i].slope_by_y(p[i + 1]);
+        for y in i32_range(p[i].y, p[i + 1].y) {
+            draw_line_horizontal(framebuffer, long, short, y, fs, blend, id);
+            long += long_slope;
+            short += short_slope;
+        }
+    }
+}
+
+/// Evaluates the edge function of the line from `a` to `b` at a point. It is
+/// twice the signed area of the triangle formed by the line and the point.
+fn edge_function<V>(a: &Fragment<V>, b: &Fragment<V>, x: f32, y: f32) -> f32 {
+    (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+}
+
+/// Rasterizes a triangle into a multisampled framebuffer. Coverage and depth
+/// are evaluated at every sample, but the fragment is shaded once per pixel at
+/// its center and written to each covered sample that passes the depth test.
+fn fill_triangle_multisample<V, FS>(
+    framebuffer: &mut Framebuffer,
+    triangle: &ProjectedTriangle<V>,
+    fs: Option<&FS>,
+) where
+    V: Varyings,
+    FS: FragmentShader<V>,
+{
+    let p = triangle.fragments;
+    let area = edge_function(&p[0], &p[1], p[2].x, p[2].y);
+    if area == 0.0 {
+        return;
+    }
+
+    // The weight of each vertex comes from the edge opposite to it, scaled so
+    // that points inside the triangle have positive weights summing to one.
+    let weights = |x: f32, y: f32| {
+        [
+            edge_function(&p[1], &p[2], x, y) / area,
+            edge_function(&p[2], &p[0], x, y) / area,
+            edge_function(&p[0], &p[1], x, y) / area,
+        ]
+    };
+
+    let x_min = p[0].x.min(p[1].x).min(p[2].x).floor() as i32;
+    let x_max = p[0].x.max(p[1].x).max(p[2].x).ceil() as i32;
+    let y_min = p[0].y.min(p[1].y).min(p[2].y).floor() as i32;
+    let y_max = p[0].y.max(p[1].y).max(p[2].y).ceil() as i32;
+    let offsets = framebuffer.sample_offsets();
+
+    for y in y_min..=y_max {
+        for x in x_min..=x_max {
+            let (canvas_x, canvas_y) = framebuffer.plane_to_canvas(x as f32, y as f32);
+            let Some(index) = framebuffer.index(canvas_x as i32, canvas_y as i32) else {
+                continue;
+            };
+
+            let mut passed = [None; MAX_SAMPLES];
+            for (s, &(dx, dy)) in offsets.iter().enumerate() {
+                let w = weights(x as f32 + dx, y as f32 + dy);
+                if w.iter().all(|&w| w >= 0.0) {
+                    let depth = w[0] * p[0].depth + w[1] * p[1].depth + w[2] * p[2].depth;
+                    if depth > framebuffer.depth(index, s) {
+                        passed[s] = Some(depth);
+                    }
+                }
+            }
+            if passed.iter().all(Option::is_none) {
+                continue;
+            }
+
+            let f = Fragment::barycentric(&p, weights(x as f32, y as f32));
+            let Some(color) = shade_fragment(framebuffer, &f, fs) else {
+                continue;
+            };
+            for (s, depth) in passed.iter().enumerate() {
+                if let Some(depth) = *depth {
+                    let (blend, id) = (triangle.blend, triangle.id);
+                    write_sample(framebuffer, index, s, depth, color, blend, id);
+                }
+            }
+        }
+    }
+}
+
+/// Finds the largest change in depth per pixel across a projected triangle.
+fn max_depth_slope<V>(p: &[Fragment<V>; 3]) -> f32 {
+    let (e1x, e1y, e1d) = (p[1].x - p[0].x, p[1].y - p[0].y, p[1].depth - p[0].depth);
+    let (e2x, e2y, e2d) = (p[2].x - p[0].x, p[2].y - p[0].y, p[2].depth - p[0].depth);
+    let nx = e1y * e2d - e1d * e2y;
+    let ny = e1d * e2x - e1x * e2d;
+    let nz = e1x * e2y - e1y * e2x;
+    if nz == 0.0 {
+        0.0
+    } else {
+        (nx / nz).abs().max((ny / nz).abs())
+    }
+}
+
+/// Draws a line between two fragments. When `depth_slope` is given, the line
+/// is depth tested with a polygon offset based on the slope so that it wins
+/// against the faces it lies on. Lines never write to the depth buffer.
+fn draw_edge<V: Varyings>(
+    framebuffer: &mut Framebuffer,
+    f0: Fragment<V>,
+    f1: Fragment<V>,
+    color: ColorF32,
+    line_mode: LineMode,
+    depth_slope: Option<f32>,
+) {
+    let p0 = framebuffer.plane_to_canvas(f0.x, f0.y);
+    let p1 = framebuffer.plane_to_canvas(f1.x, f1.y);
+    rasterize_line(line_mode, p0, p1, |x, y, t, coverage| {
+        if let Some(index) = framebuffer.index(x, y) {
+            let depth = f0.depth + (f1.depth - f0.depth) * t;
+            for s in 0..framebuffer.samples() as usize {
+                if let Some(slope) = depth_slope {
+                    let offset = LINE_OFFSET_FACTOR * slope + LINE_OFFSET_UNITS * depth;
+                    if depth + offset < framebuffer.depth(index, s) {
+                        continue;
+                    }
+                }
+                let c = color.with_alpha(color.alpha() * coverage);
+                framebuffer.blend(index, s, c, BlendMode::Alpha);
+            }
+        }
+    });
+}
+
+/// Draws the edges of a triangle in a single color.
+pub(crate) fn draw_wireframe_triangle<V: Varyings>(
+    framebuffer: &mut Framebuffer,
+    triangle: &ProjectedTriangle<V>,
+    color: ColorF32,
+    options: &RenderOptions,
+) {
+    let start = Instant::now();
+    let p = triangle.fragments;
+    let depth_slope = match options.depth_test_lines {
+        Switch::On => Some(max_depth_slope(&p)),
+        Switch::Off => None,
+    };
+    for (f0, f1) in [(p[0], p[1]), (p[1], p[2]), (p[2], p[0])] {
+        draw_edge(framebuffer, f0, f1, color, options.line_mode, depth_slope);
+    }
+    framebuffer.stats_mut().rasterize_time += start.elapsed();
+}
+
+/// Walks the scene graph, culling back faces of the models it places, then
+/// runs the vertex shader over the remaining triangles, and clips and projects
+/// the results, producing triangles in plane space ready to be rasterized.
+/// Fails if the scene is not valid.
+pub(crate) fn project_scene<VS: VertexShader>(
+    framebuffer: &mut Framebuffer,
+    scene: &Scene,
+    options: &RenderOptions,
+    vs: &VS,
+) -> Result<Vec<ProjectedTriangle<VS::Varyings>>, RenderError> {
+    scene.validate()?;
+    Ok(project_triangles(framebuffer, scene, options, vs))
+}
+
+/// Projects the triangles of a scene as `project_scene` does, without
+/// checking the scene first.
+fn project_triangles<VS: VertexShader>(
+    framebuffer: &mut Framebuffer,
+    scene: &Scene,
+    options: &RenderOptions,
+    vs: &VS,
+) -> Vec<ProjectedTriangle<VS::Varyings>> {
+    let mut projected = Vec::new();
+    let projection = create_projection_transform(&scene.camera);
+    let camera_transform = create_camera_transform(&scene.camera);
+
+    for (instance_index, instance) in scene.instances.iter().enumerate() {
+        let Some(model_index) = instance.model_index else {
+            continue;
+        };
+        let model = &scene.models[model_index];
+        let material = scene.materials[instance.material_index];
+        let model_view = camera_transform * instance.world;
+        let uniforms = Uniforms {
+            model_view,
+            normal_matrix: model_view.normal_matrix(),
+            projection,
+            opacity: material.opacity,
+            instance: instance_index,
+        };
+        for (triangle_index, triangle) in model.triangles.iter().enumerate() {
+            framebuffer.stats_mut().triangles_submitted += 1;
+            let mut stage = Instant::now();
+            // back-face culling
+            if options.cull_backfaces == Switch::On {
+                let normal = uniforms.model_view * model.normals[triangle_index];
+                // camera always at origin.
+                let view_vector = uniforms.model_view * model.vertices[triangle.vertices[0]];
+                let back_facing = normal.dot(view_vector) >= 0.0;
+                framebuffer.stats_mut().cull_time += lap(&mut stage);
+                if back_facing {
+                    framebuffer.stats_mut().triangles_culled += 1;
+                    continue;
+                }
+            }
+
+            let clip_triangle_data = [0, 1, 2].map(|i| {
+                let vertex = VertexInput {
+                    position: model.vertices[triangle.vertices[i]],
+                    normal: model.normals[triangle_index],
+                    color: model.colors[triangle.indices_color[i]],
+                    uv: triangle
+                        .indices_uv
+                        .map_or([0.0; 2], |uvs| model.uvs[uvs[i]]),
+                    triangle: triangle_index,
+                    corner: i,
+                };
+                vs.shade(&uniforms, &vertex)
+            });
+            framebuffer.stats_mut().transform_time += lap(&mut stage);
+
+            let polygon = clip_triangle(clip_triangle_data);
+            if polygon.is_empty() {
+                framebuffer.stats_mut().triangles_clipped += 1;
+            }
+            framebuffer.stats_mut().clip_time += lap(&mut stage);
+            for clipped_triangle in polygon.triangles() {
+                projected.push(ProjectedTriangle {
+                    fragments: clipped_triangle.map(|v| projected_to_point(framebuffer, v)),
+                    blend: material.blend,
+                    id: PrimitiveId {
+                        instance: instance_index,
+                        triangle: triangle_index,
+                    },
+                });
+            }
+            framebuffer.stats_mut().project_time += lap(&mut stage);
+        }
+    }
+
+    projected
+}
+
+/// Orders triangles so that opaque triangles come first, in their original
+/// order, followed by blended triangles from back to front.
+fn sort_for_blending<V>(triangles: &mut [ProjectedTriangle<V>]) {
+    triangles.sort_by(|t, u| {
+        let t_opaque = t.blend == BlendMode::Opaque;
+        let u_opaque = u.blend == BlendMode::Opaque;
+        match (t_opaque, u_opaque) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            // Smaller depths are farther away.
+            (false, false) => t.mean_depth().total_cmp(&u.mean_depth()),
+        }
+    });
+}
+
+/// Draws a scene into a framebuffer. Fails, drawing nothing, if the scene is
+/// not valid.
+pub fn render_scene(
+    framebuffer: &mut Framebuffer,
+    scene: &Scene,
+    options: &RenderOptions,
+) -> RenderResult {
+    scene.validate()?;
+    match options.draw {
+        Draw::Depths => {
+            // Only depths are written here. They are turned into colors once
+            // the whole frame is known, by `depth_image`.
+            let depth_only: Option<&DepthShader> = None;
+            for t in project_triangles(framebuffer, scene, options, &DepthShader) {
+                fill_triangle(framebuffer, &t, depth_only);
+            }
+        }
+        Draw::Pixels | Draw::Wireframe | Draw::Overlay => {
+            let shader = &VertexColorShader;
+            draw_scene_with(framebuffer, scene, options, shader, shader);
+        }
+        Draw::Normals => {
+            let shader = &NormalShader;
+            render_debug_view(framebuffer, scene, options, shader, BlendMode::Opaque);
+        }
+        Draw::TriangleIds => {
+            let shader = &IdShader::Triangle;
+            render_debug_view(framebuffer, scene, options, shader, BlendMode::Opaque);
+        }
+        Draw::InstanceIds => {
+            let shader = &IdShader::Instance;
+            render_debug_view(framebuffer, scene, options, shader, BlendMode::Opaque);
+        }
+        Draw::Barycentrics => {
+            let shader = &BarycentricShader;
+            render_debug_view(framebuffer, scene, options, shader, BlendMode::Opaque);
+        }
+        Draw::Overdraw => {
+            // Additive fragments never write depth, so every fragment passes
+            // the depth test and adds to the count.
+            let shader = &OverdrawShader;
+            render_debug_view(framebuffer, scene, options, shader, BlendMode::Additive);
+        }
+        Draw::RayTraced => raytrace::render(framebuffer, scene),
+    }
+
+    draw_highlight(framebuffer, scene, options);
+    Ok(())
+}
+
+/// Draws a scene with a vertex shader and a fragment shader, the way
+/// `render_scene` draws it with the model's vertex colors. Triangles are
+/// blended by their materials, and drawn as wireframes or overlays when
+/// `options.draw` says so, or filled otherwise. Fails, drawing nothing, if
+/// the scene is not valid.
+pub fn render_scene_with<VS, FS>(
+    framebuffer: &mut Framebuffer,
+    scene: &Scene,
+    options: &RenderOptions,
+    vs: &VS,
+    fs: &FS,
+) -> RenderResult
+where
+    VS: VertexShader,
+    FS: FragmentShader<VS::Varyings>,
+{
+    scene.validate()?;
+    draw_scene_with(framebuffer, scene, options, vs, fs);
+    draw_highlight(framebuffer, scene, options);
+    Ok(())
+}
+
+/// Outlines the highlighted instance, if there is one.
+fn draw_highlight(framebuffer: &mut Framebuffer, scene: &Scene, options: &RenderOptions) {
+    let Some(instance) = options.highlight else {
+        return;
+    };
+    // The scene was already projected for the frame, so projecting it again
+    // for the outline is left out of the stats.
+    let stats = *framebuffer.stats();
+    let triangles = project_triangles(framebuffer, scene, options, &DepthShader);
+    *framebuffer.stats_mut() = stats;
+    for t in triangles.iter().filter(|t| t.id.instance == instance) {
+        draw_wireframe_triangle(framebuffer, t, ColorF32::YELLOW, options);
+    }
+}
+
+/// Fills the triangles of a scene with a debug shader, drawing them all with
+/// one blend mode regardless of their materials.
+fn render_debug_view<S>(
+    framebuffer: &mut Framebuffer,
+    scene: &Scene,
+    options: &RenderOptions,
+    shader: &S,
+    blend: BlendMode,
+) where
+    S: VertexShader + FragmentShader<S::Varyings>,
+{
+    for mut t in project_triangles(framebuffer, scene, options, shader) {
+        t.blend = blend;
+        fill_triangle(framebuffer, &t, Some(shader));
+    }
+}
+
+/// Draws a scene as `render_scene_with` does, without checking the scene
+/// first. In wireframe mode, each edge takes the color the fragment shader
+/// gives the triangle's first vertex.
+fn draw_scene_with<VS, FS>(
+    framebuffer: &mut Framebuffer,
+    scene: &Scene,
+    options: &RenderOptions,
+    vs: &VS,
+    fs: &FS,
+) where
+    VS: VertexShader,
+    FS: FragmentShader<VS::Varyings>,
+{
+    let mut triangles = project_triangles(framebuffer, scene, options, vs);
+    sort_for_blending(&mut triangles);
+
+    match options.draw {
+        Draw::Wireframe => {
+            if options.depth_test_lines == Switch::On {
+                let depth_only: Option<&FS> = None;
+                for t in triangles.iter() {
+                    fill_triangle(framebuffer, t, depth_only);
+                }
+            }
+            for t in triangles.iter() {
+                if let Some(color) = fs.shade(&t.fragments[0].to_input()) {
+                    draw_wireframe_triangle(framebuffer, t, color, options);
+                }
+            }
+        }
+        Draw::Overlay => {
+            for t in triangles.iter() {
+                fill_triangle(framebuffer, t, Some(fs));
+            }
+            for t in triangles.iter() {
+                draw_wireframe_triangle(framebuffer, t, ColorF32::WHITE, options);
+            }
+        }
+        _ => {
+            for t in triangles.iter() {
+                fill_triangle(framebuffer, t, Some(fs));
+            }
+        }
+    }
+}
+
+/// Turns the depth buffer into an image. Depths are mapped linearly from the
+/// camera's near and far planes, or from the nearest and farthest depths
+/// drawn, to brightness with nearer depths brighter.
+pub fn depth_image(framebuffer: &Framebuffer, camera: &Camera, options: &RenderOptions) -> Image {
+    let depths = framebuffer.camera_depths();
+    let (near, far) = match options.auto_range_depths {
+        Switch::On => depths
+            .iter()
+            .filter(|z| z.is_finite())
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(near, far), &z| {
+                (near.min(z), far.max(z))
+            }),
+        Switch::Off => (camera.near, camera.far),
+    };
+
+    let mut image = Image::new(framebuffer.width(), framebuffer.height());
+    for (pixel, &z) in image.pixels_mut().iter_mut().zip(depths.iter()) {
+        if z.is_infinite() {
+            continue;
+        }
+        let brightness = if far > near {
+            1.0 - (z - near) / (far - near)
+        } else {
+            1.0
+        };
+        *pixel = match options.false_color_depths {
+            Switch::On => ColorF32::heatmap(brightness),
+            Switch::Off => ColorF32::new(brightness, brightness, brightness),
+        };
+    }
+    image
+}
+
+/// Creates the world space ray from the camera through a point of the
+/// framebuffer in plane space. The ray's parameter at a point is the point's
+/// camera space depth.
+pub fn camera_ray(framebuffer: &Framebuffer, camera: &Camera, x: f32, y: f32) -> Option<Ray> {
+    // Undo the projection of the point at a depth of one.
+    let direction = Vec4::new(
+        x * VIEWPORT_WIDTH / (D * framebuffer.width() as f32),
+        y * VIEWPORT_HEIGHT / (D * framebuffer.height() as f32),
+        1.0,
+        0.0,
+    );
+    let ray = Ray::new(Vec4::new(0.0, 0.0, 0.0, 1.0), direction);
+    Some(ray.transform(&create_camera_transform(camera).inverse()?))
+}
+
+/// Finds the instance and triangle drawn at a pixel of the framebuffer in
+/// canvas space in the last rendered frame, and where in the world it was hit.
+pub fn pick(framebuffer: &Framebuffer, camera: &Camera, x: i32, y: i32) -> Option<Pick> {
+    let (id, depth) = framebuffer.pick(x, y)?;
+    let (plane_x, plane_y) = framebuffer.canvas_to_plane(x as f32, y as f32);
+    let ray = camera_ray(framebuffer, camera, plane_x, plane_y)?;
+    Some(Pick {
+        instance: id.instance,
+        triangle: id.triangle,
+        point: ray.at(1.0 / depth),
+    })
+}
+
+/// Renders a frame and downsamples it from the framebuffer's size to the
+/// canvas size. Fails if the scene is not valid.
+pub fn render_image(
+    framebuffer: &mut Framebuffer,
+    scene: &Scene,
+    options: &RenderOptions,
+) -> Result<Image, RenderError> {
+    framebuffer.clear(ColorF32::BLACK);
+    render_scene(framebuffer, scene, options)?;
+    let start = Instant::now();
+    framebuffer.resolve();
+    let depths;
+    let resolved = match options.draw {
+        Draw::Depths => {
+            depths = depth_image(framebuffer, &scene.camera, options);
+            &depths
+        }
+        _ => framebuffer.image(),
+    };
+    let mut image = resolved.downsample(options.supersample, options.filter);
+    if options.draw == Draw::Overdraw {
+        for pixel in image.pixels_mut() {
+            *pixel = ColorF32::heatmap(pixel.rgb().0);
+        }
+    }
+    framebuffer.stats_mut().resolve_time += start.elapsed();
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shows texture coordinates as red and green.
+    struct UvShader;
+
+    impl VertexShader for UvShader {
+        type Varyings = [f32; 2];
+
+        fn shade(&self, uniforms: &Uniforms, vertex: &VertexInput) -> ClipVertex<[f32; 2]> {
+            ClipVertex::new(uniforms.clip_position(vertex.position), vertex.uv)
+        }
+    }
+
+    impl FragmentShader<[f32; 2]> for UvShader {
+        fn shade(&self, fragment: &FragmentInput<[f32; 2]>) -> Option<ColorF32> {
+            let [u, v] = fragment.varyings;
+            Some(ColorF32::new(u, v, 1.0))
+        }
+    }
+
+    #[test]
+    fn custom_shaders_see_texture_coordinates() {
+        let mut scene = demo().unwrap();
+        scene.animate(2.0);
+        for instance in scene.instances.iter_mut() {
+            instance.material_index = 0;
+        }
+        let model = &mut scene.models[0];
+        model.uvs = vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0]];
+        for triangle in model.triangles.iter_mut() {
+            *triangle = triangle.clone().with_uvs([0, 1, 2]);
+        }
+
+        let options = RenderOptions::default();
+        let mut framebuffer = Framebuffer::new(64, 64, 1).unwrap();
+        framebuffer.clear(ColorF32::BLACK);
+        render_scene_with(&mut framebuffer, &scene, &options, &UvShader, &UvShader).unwrap();
+        framebuffer.resolve();
+        let drawn: Vec<(f32, f32, f32)> = framebuffer
+            .image()
+            .pixels()
+            .iter()
+            .map(|p| p.rgb())
+            .filter(|&(_, _, b)| b > 0.0)
+            .collect();
+        assert!(!drawn.is_empty());
+        assert!(drawn.iter().any(|&(u, _, _)| u > 0.5));
+        assert!(drawn.iter().any(|&(_, v, _)| v > 0.5));
+
+        scene.models[0].triangles[0] = ModelTriangle::new([0, 1, 2], [0, 0, 0]).with_uvs([0, 1, 3]);
+        let result = render_scene_with(&mut framebuffer, &scene, &options, &UvShader, &UvShader);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn highlighting_leaves_stats_unchanged() {
+        let mut scene = demo().unwrap();
+        scene.animate(2.0);
+        let mut framebuffer = Framebuffer::new(64, 64, 1).unwrap();
+        let counts = |framebuffer: &mut Framebuffer, highlight| {
+            let options = RenderOptions {
+                highlight,
+                ..RenderOptions::default()
+            };
+            render_image(framebuffer, &scene, &options).unwrap();
+            let stats = framebuffer.stats();
+            [
+                stats.triangles_submitted,
+                stats.triangles_culled,
+                stats.triangles_clipped,
+                stats.triangles_rasterized,
+                stats.pixels_shaded,
+            ]
+        };
+        let plain = counts(&mut framebuffer, None);
+        assert!(plain[0] > 0);
+        assert_eq!(counts(&mut framebuffer, Some(0)), plain);
+    }
+
+    #[test]
+    fn invalid_scenes_fail_to_render() {
+        let mut framebuffer = Framebuffer::new(64, 64, 1).unwrap();
+        let mut scene = demo().unwrap();
+        assert!(render_image(&mut framebuffer, &scene, &RenderOptions::default()).is_ok());
+
+        // Models can be changed after `Model::new` checked them.
+        scene.models[0].triangles[3].vertices[1] = 8;
+        let result = render_image(&mut framebuffer, &scene, &RenderOptions::default());
+        assert!(matches!(
+            result,
+            Err(RenderError::InvalidModel { model: 0, .. })
+        ));
+
+        let mut scene = demo().unwrap();
+        scene.instances[2].material_index = scene.materials.len();
+        for draw in [Draw::Pixels, Draw::RayTraced] {
+            let options = RenderOptions {
+                draw,
+                ..RenderOptions::default()
+            };
+            let result = render_scene(&mut framebuffer, &scene, &options);
+            assert!(matches!(
+                result,
+                Err(RenderError::InvalidMaterialIndex { instance: 2, .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,654 @@
+//! Scenes of instanced models, and the transforms that place them.
+
+use crate::animation::{self, Clip};
+use crate::bvh::{Aabb, Bvh};
+use crate::error::RenderError;
+use crate::gfx::{BlendMode, ColorF32};
+use crate::math::mat::*;
+use crate::math::quat::*;
+use crate::math::transform::*;
+use crate::math::vec::*;
+use crate::ray::*;
+
+/// The width of the viewport in camera space, which the projection maps onto
+/// the whole canvas.
+pub const VIEWPORT_WIDTH: f32 = 1.0;
+/// The height of the viewport in camera space.
+pub const VIEWPORT_HEIGHT: f32 = 1.0;
+
+/// The distance from the camera to the viewport.
+pub const D: f32 = 1.0;
+
+/// The animation clips of the demo scene.
+const DEMO_ANIMATION: &str = include_str!("../animations/default.anim");
+
+/// The point of view a scene is rendered from. The camera looks along the z
+/// axis from its translation, turned by the x, y and z angles of its rotation
+/// in radians, and sees depths between `near` and `far`.
+pub struct Camera {
+    /// The position in world space.
+    pub translation: Vec4,
+    /// The angles about the x, y and z axes in radians.
+    pub rotation: Vec3,
+    /// The nearest depth seen, which must be positive.
+    pub near: f32,
+    /// The farthest depth seen, which must be finite and beyond `near`.
+    pub far: f32,
+}
+
+/// Surface properties that control how an instance is blended into the frame.
+#[derive(Clone, Copy)]
+pub struct Material {
+    /// How the instance's colors combine with the colors behind them.
+    pub blend: BlendMode,
+    /// Multiplies the alpha of the model's colors.
+    pub opacity: f32,
+    /// The fraction of light mirrored off the surface. Only the ray tracer
+    /// renders reflections.
+    pub reflectivity: f32,
+}
+
+/// A point light. Only the ray tracer lights scenes.
+#[derive(Clone, Copy)]
+pub struct Light {
+    /// The position in world space.
+    pub position: Vec4,
+    /// The light reaching a surface that faces the light.
+    pub intensity: f32,
+}
+
+/// A triangle mesh. Its normals and hierarchy of bounds are computed from its
+/// triangles by `Model::new`, which also checks that the triangles are valid.
+pub struct Model {
+    /// The corners of the triangles in model space.
+    pub vertices: Vec<Vec4>,
+    /// The colors of the triangles' corners.
+    pub colors: Vec<ColorF32>,
+    /// Texture coordinates, which only triangles with `indices_uv` use.
+    pub uvs: Vec<[f32; 2]>,
+    /// The triangles, as indices into the other lists.
+    pub triangles: Vec<ModelTriangle>,
+    /// The unit normal of each triangle in model space, in the direction of
+    /// the cross product of its edges from its first corner.
+    pub normals: Vec<Vec4>,
+    /// Organizes the triangles in model space for ray queries.
+    pub bvh: Bvh,
+}
+
+/// Models placed in the world by a graph of instances, along with the camera
+/// and lights they are seen with.
+pub struct Scene {
+    /// The meshes that instances draw.
+    pub models: Vec<Model>,
+    /// The materials that instances draw their models with.
+    pub materials: Vec<Material>,
+    /// The nodes of the scene graph, with parents before their children.
+    pub instances: Vec<Instance>,
+    /// The point of view the scene is rendered from.
+    pub camera: Camera,
+    /// The lights the ray tracer lights the scene with.
+    pub lights: Vec<Light>,
+    /// The light reaching every surface regardless of the lights.
+    pub ambient: f32,
+    /// Organizes the instances by their world space bounds for ray queries.
+    pub instance_bvh: Bvh,
+    /// The animation clips, which all play at once.
+    pub clips: Vec<Clip>,
+}
+
+/// A triangle of a model, given by the indices of its corners' vertices and
+/// their colors, and optionally their texture coordinates.
+#[derive(Clone)]
+pub struct ModelTriangle {
+    /// The indices of the corners in `Model::vertices`.
+    pub vertices: [usize; 3],
+    /// The indices of the corners' colors in `Model::colors`.
+    pub indices_color: [usize; 3],
+    /// Triangles without texture coordinates give shaders zeros instead.
+    pub indices_uv: Option<[usize; 3]>,
+}
+
+/// A node of the scene graph. Its translation, scaling and rotation place it
+/// relative to its parent, and it draws a model unless it only groups its
+/// children.
+pub struct Instance {
+    /// The index of the model drawn in `Scene::models`, if any.
+    pub model_index: Option<usize>,
+    /// The index of the material the model is drawn with in
+    /// `Scene::materials`.
+    pub material_index: usize,
+    /// The instance this one is placed relative to. Parents must come before
+    /// their children in `Scene::instances`.
+    pub parent: Option<usize>,
+    /// The position relative to the parent.
+    pub translation: Vec4,
+    /// The scale factor along each axis.
+    pub scaling: Vec3,
+    /// The orientation relative to the parent.
+    pub rotation: Quat,
+    /// Transforms from model space to world space, through all of the
+    /// instance's parents. It is computed by `Scene::update_world`.
+    pub world: Mat4,
+}
+
+/// The nearest surface a ray hits in a scene.
+pub struct RayHit {
+    /// The index of the instance hit in `Scene::instances`.
+    pub instance: usize,
+    /// The index of the triangle hit in the instance's model.
+    pub triangle: usize,
+    /// The ray parameter of the hit, which is the distance along the ray in
+    /// multiples of its direction's length.
+    pub distance: f32,
+    /// The point hit in world space.
+    pub point: Vec4,
+    /// The weights of the triangle's vertices at the point hit.
+    pub barycentrics: [f32; 3],
+}
+
+impl Material {
+    /// Creates a material from how it blends, its opacity and its
+    /// reflectivity.
+    pub fn new(blend: BlendMode, opacity: f32, reflectivity: f32) -> Self {
+        Material {
+            blend,
+            opacity,
+            reflectivity,
+        }
+    }
+}
+
+impl Instance {
+    /// Creates an instance of a model, or of nothing, at the origin of the
+    /// world without a parent, using the first material.
+    pub fn new(model_index: Option<usize>) -> Self {
+        Instance {
+            model_index,
+            material_index: 0,
+            parent: None,
+            translation: Vec4::new(0.0, 0.0, 0.0, 0.0),
+            scaling: Vec3::new(1.0, 1.0, 1.0),
+            rotation: Quat::IDENTITY,
+            world: Mat4::IDENTITY,
+        }
+    }
+}
+
+impl Model {
+    /// Creates a model, failing if a triangle uses a vertex, color or texture
+    /// coordinates the model does not have, or has no normal.
+    pub fn new(
+        vertices: Vec<Vec4>,
+        colors: Vec<ColorF32>,
+        uvs: Vec<[f32; 2]>,
+        triangles: Vec<ModelTriangle>,
+    ) -> Result<Self, RenderError> {
+        check_indices(&triangles, vertices.len(), colors.len(), uvs.len())?;
+        let mut normals = Vec::new();
+        for (i, triangle) in triangles.iter().enumerate() {
+            let v1 = vertices[triangle.vertices[1]] - vertices[triangle.vertices[0]];
+            let v2 = vertices[triangle.vertices[2]] - vertices[triangle.vertices[0]];
+            let cross = v1.cross(v2);
+            // Zero for triangles without area, and NaN or infinite for
+            // corners that are not finite.
+            if !cross.magnitude().is_normal() {
+                return Err(RenderError::DegenerateTriangle { triangle: i });
+            }
+            normals.push(cross.normalize());
+        }
+        let triangle_bounds: Vec<Aabb> = triangles
+            .iter()
+            .map(|t| Aabb::from_points(t.vertices.map(|i| vertices[i])))
+            .collect();
+        let bvh = Bvh::build(&triangle_bounds);
+        Ok(Model {
+            vertices,
+            colors,
+            uvs,
+            triangles,
+            normals,
+            bvh,
+        })
+    }
+
+    /// Checks that every triangle uses vertices, colors and texture
+    /// coordinates the model has, and has a normal, as `Model::new` does for
+    /// the model it creates. Models changed since need checking again before
+    /// they are rendered.
+    pub fn validate(&self) -> Result<(), RenderError> {
+        let (vertices, colors, uvs) = (self.vertices.len(), self.colors.len(), self.uvs.len());
+        check_indices(&self.triangles, vertices, colors, uvs)?;
+        if self.normals.len() != self.triangles.len() {
+            return Err(RenderError::InvalidNormalCount {
+                count: self.normals.len(),
+                triangles: self.triangles.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Checks that triangles only use the vertices, colors and texture
+/// coordinates there are.
+fn check_indices(
+    triangles: &[ModelTriangle],
+    vertex_count: usize,
+    color_count: usize,
+    uv_count: usize,
+) -> Result<(), RenderError> {
+    for (i, triangle) in triangles.iter().enumerate() {
+        if let Some(&index) = triangle.vertices.iter().find(|&&v| v >= vertex_count) {
+            return Err(RenderError::InvalidVertexIndex {
+                triangle: i,
+                index,
+                count: vertex_count,
+            });
+        }
+        if let Some(&index) = triangle.indices_color.iter().find(|&&c| c >= color_count) {
+            return Err(RenderError::InvalidColorIndex {
+                triangle: i,
+                index,
+                count: color_count,
+            });
+        }
+        if let Some(&index) = triangle
+            .indices_uv
+            .iter()
+            .flatten()
+            .find(|&&t| t >= uv_count)
+        {
+            return Err(RenderError::InvalidUvIndex {
+                triangle: i,
+                index,
+                count: uv_count,
+            });
+        }
+    }
+    Ok(())
+}
+
+impl Scene {
+    /// Poses the instances as the clips have them at a time in seconds.
+    pub fn animate(&mut self, time: f32) {
+        for clip in self.clips.iter() {
+            clip.apply(&mut self.instances, time);
+        }
+        self.update_world();
+    }
+
+    /// Checks that the camera has a projection, that the models are valid,
+    /// that every instance uses a model and material the scene has and comes
+    /// after its parent, and that the clips only animate instances the scene
+    /// has. Scenes are checked before they are rendered, failing with the
+    /// same error.
+    pub fn validate(&self) -> Result<(), RenderError> {
+        let Camera { near, far, .. } = self.camera;
+        // Written so that NaN depths fail too.
+        if !(near > 0.0 && far > near && far.is_finite()) {
+            return Err(RenderError::InvalidCamera { near, far });
+        }
+        for (i, model) in self.models.iter().enumerate() {
+            model.validate().map_err(|e| RenderError::InvalidModel {
+                model: i,
+                source: Box::new(e),
+            })?;
+        }
+        for (i, instance) in self.instances.iter().enumerate() {
+            if let Some(parent) = instance.parent.filter(|&p| p >= i) {
+                return Err(RenderError::InvalidParent {
+                    instance: i,
+                    parent,
+                });
+            }
+            // Instances without a model are never drawn, so their material
+            // is never used.
+            let Some(model_index) = instance.model_index else {
+                continue;
+            };
+            if model_index >= self.models.len() {
+                return Err(RenderError::InvalidModelIndex {
+                    instance: i,
+                    index: model_index,
+                    count: self.models.len(),
+                });
+            }
+            if instance.material_index >= self.materials.len() {
+                return Err(RenderError::InvalidMaterialIndex {
+                    instance: i,
+                    index: instance.material_index,
+                    count: self.materials.len(),
+                });
+            }
+        }
+        for (i, clip) in self.clips.iter().enumerate() {
+            if let Some(&(instance, _)) = clip
+                .channels
+                .iter()
+                .find(|(index, _)| *index >= self.instances.len())
+            {
+                return Err(RenderError::InvalidClipInstance {
+                    clip: i,
+                    instance,
+                    count: self.instances.len(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Propagates transforms down the scene graph and updates the instance
+    /// hierarchy. It must be called after instances move. Instances with a
+    /// parent or model the scene does not have, which `validate` rejects, are
+    /// placed as if they had none.
+    pub fn update_world(&mut self) {
+        for i in 0..self.instances.len() {
+            let local = create_instance_transform(&self.instances[i]);
+            let parent = self.instances[i].parent.and_then(|p| self.instances.get(p));
+            self.instances[i].world = match parent {
+                Some(parent) => parent.world * local,
+                None => local,
+            };
+        }
+
+        // Instances without a model get a point at their origin, which keeps
+        // their indices in the hierarchy without rays ever hitting them.
+        let bounds: Vec<Aabb> = self
+            .instances
+            .iter()
+            .map(|instance| {
+                let model = instance.model_index.and_then(|m| self.models.get(m));
+                match model {
+                    Some(model) => model.bvh.bounds().transform(&instance.world),
+                    None => Aabb::from_points([instance.world * Vec4::new(0.0, 0.0, 0.0, 1.0)]),
+                }
+            })
+            .collect();
+        self.instance_bvh.update(&bounds);
+    }
+
+    /// Finds the nearest triangle a ray in world space hits, using the
+    /// instance hierarchy to find the instances the ray may hit.
+    pub fn raycast(&self, origin: Vec4, direction: Vec4) -> Option<RayHit> {
+        let ray = Ray::new(origin, direction);
+        let mut nearest = None;
+        self.instance_bvh
+            .traverse(&ray, f32::INFINITY, |instance_index, max_t| {
+                let hit = self.raycast_instance(&ray, instance_index, max_t)?;
+                let t = hit.distance;
+                nearest = Some(hit);
+                Some(t)
+            });
+        nearest
+    }
+
+    /// Finds the nearest triangle of an instance a ray hits before `max_t`,
+    /// using the model's hierarchy to find the triangles the ray may hit.
+    fn raycast_instance(&self, ray: &Ray, instance_index: usize, max_t: f32) -> Option<RayHit> {
+        let instance = &self.instances[instance_index];
+        let model = self.models.get(instance.model_index?)?;
+        let inverse = instance.world.inverse()?;
+
+        // Intersecting in model space leaves the ray parameter unchanged.
+        let model_ray = ray.transform(&inverse);
+        let mut nearest = None;
+        model
+            .bvh
+            .traverse(&model_ray, max_t, |triangle_index, max_t| {
+                let triangle = &model.triangles[triangle_index];
+                let vertices = triangle.vertices.map(|i| model.vertices[i]);
+                let (t, barycentrics) = intersect_triangle(&model_ray, vertices)?;
+                if t >= max_t {
+                    return None;
+                }
+                nearest = Some(RayHit {
+                    instance: instance_index,
+                    triangle: triangle_index,
+                    distance: t,
+                    point: ray.at(t),
+                    barycentrics,
+                });
+                Some(t)
+            });
+        nearest
+    }
+}
+
+impl ModelTriangle {
+    /// Creates a triangle from the indices of its corners' vertices and
+    /// colors, without texture coordinates.
+    pub fn new(vertices: [usize; 3], indices_color: [usize; 3]) -> Self {
+        ModelTriangle {
+            vertices,
+            indices_color,
+            indices_uv: None,
+        }
+    }
+
+    /// Gives the triangle's corners texture coordinates.
+    pub fn with_uvs(self, indices_uv: [usize; 3]) -> Self {
+        ModelTriangle {
+            indices_uv: Some(indices_uv),
+            ..self
+        }
+    }
+}
+
+/// Builds the demo scene: four cubes circling the view direction in different
+/// materials, one of them with a small cube orbiting it.
+pub fn demo() -> Result<Scene, RenderError> {
+    let vertices = vec![
+        Vec4::new(1.0, 1.0, 1.0, 1.0), // 0 - black(0)
+        Vec4::new(-1.0, 1.0, 1.0, 1.0), // 1 - red(0)
+        Vec4::new(-1.0, -1.0, 1.0, 1.0), // 2 - yellow(3)
+        Vec4::new(1.0, -1.0, 1.0, 1.0), // 3 - green(1)
+        Vec4::new(1.0, 1.0, -1.0, 1.0), // 4 - blue(2)
+        Vec4::new(-1.0, 1.0, -1.0, 1.0), // 5 - magenta(4)
+        Vec4::new(-1.0, -1.0, -1.0, 1.0), // 6 - white (0)
+        Vec4::new(1.0, -1.0, -1.0, 1.0), // 7 - cyan(5)
+    ];
+    
+    let colors = vec![
+        ColorF32::RED, // 0
+        ColorF32::GREEN, // 1
+        ColorF32::BLUE, // 2
+        ColorF32::YELLOW, // 3
+        ColorF32::MAGENTA, // 4
+        ColorF32::CYAN, // 5
+        ColorF32::WHITE, // 6
+        ColorF32::BLACK, // 7
+    ];
+
+    let triangles = vec![
+        ModelTriangle::new([0, 1, 2], [7, 0, 3]),
+        ModelTriangle::new([0, 2, 3], [7, 3, 1]),
+        ModelTriangle::new([4, 0, 3], [2, 7, 1]),
+        ModelTriangle::new([4, 3, 7], [2, 1, 5]),
+        ModelTriangle::new([5, 4, 7], [4, 2, 5]),
+        ModelTriangle::new([5, 7, 6], [4, 5, 6]),
+        ModelTriangle::new([1, 5, 6], [0, 4, 6]),
+        ModelTriangle::new([1, 6, 2], [0, 6, 3]),
+        ModelTriangle::new([4, 5, 1], [2, 4, 0]),
+        ModelTriangle::new([4, 1, 0], [2, 0, 7]),
+        ModelTriangle::new([2, 6, 7], [3, 6, 5]),
+        ModelTriangle::new([2, 7, 3], [3, 5, 1]),
+    ];
+
+    let models = vec![Model::new(vertices, colors, Vec::new(), triangles)?];
+
+    let materials = vec![
+        Material::new(BlendMode::Opaque, 1.0, 0.25),
+        Material::new(BlendMode::Alpha, 0.5, 0.0),
+        Material::new(BlendMode::Additive, 0.7, 0.0),
+        Material::new(BlendMode::Multiply, 0.8, 0.0),
+    ];
+
+    let mut instances = vec![
+        Instance::new(Some(0)),
+        Instance::new(Some(0)),
+        Instance::new(Some(0)),
+        Instance::new(Some(0)),
+        // A small cube orbiting the first one, through a pivot that spins
+        // relative to it.
+        Instance::new(None),
+        Instance::new(Some(0)),
+    ];
+    instances[3].material_index = 1;
+    instances[4].parent = Some(0);
+    instances[5].parent = Some(4);
+    instances[5].translation = Vec4::new(1.8, 0.0, 0.0, 0.0);
+
+    let clips = animation::parse(DEMO_ANIMATION, instances.len())?;
+
+    let camera = Camera {
+        translation: Vec4::new(0.0, 0.0, 0.0, 0.0),
+        rotation: Vec3::new(0.0, 0.0, 0.0),
+        near: D,
+        far: 100.0,
+    };
+
+    let lights = vec![Light {
+        position: Vec4::new(-4.0, 5.0, 1.0, 1.0),
+        intensity: 0.8,
+    }];
+
+    let mut scene = Scene {
+        models,
+        materials,
+        instances,
+        camera,
+        lights,
+        ambient: 0.3,
+        instance_bvh: Bvh::default(),
+        clips,
+    };
+    scene.validate()?;
+    scene.update_world();
+    Ok(scene)
+}
+
+/// Creates the transform from world space to the camera space of a camera,
+/// where the camera is at the origin looking along the z axis.
+pub fn create_camera_transform(camera: &Camera) -> Mat4 {
+    let c_t = translation(-camera.translation);
+    let c_rx = rotation_x(-camera.rotation[0]);
+    let c_ry = rotation_y(-camera.rotation[1]);
+    let c_rz = rotation_z(-camera.rotation[2]);
+    c_rx * c_ry * c_rz * c_t
+}
+
+/// Creates the transform from an instance's model space to its parent's
+/// space.
+pub fn create_instance_transform(instance: &Instance) -> Mat4 {
+    let i_t = translation(instance.translation);
+    let i_s = scaling(instance.scaling);
+    let i_r = instance.rotation.to_mat4();
+    i_t * i_r * i_s
+}
+
+/// Creates the transform from camera space to the homogeneous clip space of a
+/// camera's view, as `perspective_projection` describes.
+pub fn create_projection_transform(camera: &Camera) -> Mat4 {
+    perspective_projection(D, VIEWPORT_WIDTH, VIEWPORT_HEIGHT, camera.near, camera.far)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{brute_force_raycast, Random};
+
+    /// Finds the nearest triangle a ray hits by testing every triangle of
+    /// every instance, in model space as `Scene::raycast` does.
+    fn brute_force(scene: &Scene, ray: &Ray) -> Option<(usize, usize, f32)> {
+        let hits = scene
+            .instances
+            .iter()
+            .enumerate()
+            .filter_map(|(i, instance)| {
+                let model = &scene.models[instance.model_index?];
+                let triangles: Vec<[Vec4; 3]> = model
+                    .triangles
+                    .iter()
+                    .map(|triangle| triangle.vertices.map(|v| model.vertices[v]))
+                    .collect();
+                let model_ray = ray.transform(&instance.world.inverse()?);
+                let (j, t) = brute_force_raycast(&triangles, &model_ray)?;
+                Some((i, j, t))
+            });
+        hits.min_by(|a, b| a.2.total_cmp(&b.2))
+    }
+
+    #[test]
+    fn cameras_without_a_projection_are_rejected() {
+        let mut scene = demo().unwrap();
+        let depths = [
+            (0.0, 10.0),
+            (2.0, 1.0),
+            (1.0, f32::INFINITY),
+            (f32::NAN, 10.0),
+        ];
+        for (near, far) in depths {
+            scene.camera.near = near;
+            scene.camera.far = far;
+            assert!(matches!(
+                scene.validate(),
+                Err(RenderError::InvalidCamera { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn clips_of_missing_instances_are_rejected() {
+        let mut scene = demo().unwrap();
+        scene.instances.truncate(2);
+        assert!(matches!(
+            scene.validate(),
+            Err(RenderError::InvalidClipInstance { count: 2, .. })
+        ));
+        // Animating skips the missing instances rather than panicking.
+        scene.animate(1.0);
+    }
+
+    #[test]
+    fn updated_scenes_raycast_like_fresh_ones() {
+        let mut random = Random::new(3);
+        let mut scene = demo().unwrap();
+        let mut hits = 0;
+        for step in 1..=10 {
+            let time = 0.4 * step as f32;
+            // Later steps also push the instances well away from where the
+            // hierarchy was built.
+            let offsets: Vec<Vec4> = scene
+                .instances
+                .iter()
+                .map(|_| random.vector(0.5 * step as f32))
+                .collect();
+            let mut fresh = demo().unwrap();
+            for s in [&mut scene, &mut fresh] {
+                s.animate(time);
+                for (instance, &offset) in s.instances.iter_mut().zip(&offsets) {
+                    if instance.parent.is_none() {
+                        instance.translation = instance.translation + offset;
+                    }
+                }
+            }
+            scene.update_world();
+            fresh.instance_bvh = Bvh::default();
+            fresh.update_world();
+
+            for i in 0..20 {
+                let origin = random.point(20.0);
+                let instance = &scene.instances[i % scene.instances.len()];
+                let target = instance.world * Vec4::new(0.0, 0.0, 0.0, 1.0) + random.vector(0.5);
+                let ray = Ray::new(origin, target - origin);
+                let expected = brute_force(&scene, &ray);
+                for s in [&scene, &fresh] {
+                    let hit = s.raycast(ray.origin, ray.direction);
+                    let hit = hit.map(|hit| (hit.instance, hit.triangle, hit.distance));
+                    assert_eq!(hit, expected, "step {step}, ray {i}");
+                }
+                hits += expected.is_some() as usize;
+            }
+        }
+        assert!(hits > 100, "{hits} rays hit");
+    }
+}
@@ -0,0 +1,254 @@
+//! Programmable vertex and fragment stages of the rendering pipeline.
+
+use crate::clip::ClipVertex;
+use crate::gfx::ColorF32;
+use crate::math::mat::Mat4;
+use crate::math::vec::Vec4;
+
+/// Values a vertex shader outputs for each vertex, which are interpolated
+/// across a triangle and handed to the fragment shader. Clipping and
+/// rasterization only ever combine varyings linearly, through `add` and
+/// `scale`.
+pub trait Varyings: Copy {
+    /// Adds another set of varyings to these, value by value.
+    fn add(self, rhs: Self) -> Self;
+
+    /// Multiplies every value by a factor.
+    fn scale(self, s: f32) -> Self;
+
+    /// Linearly interpolates between these varyings and another set.
+    fn lerp(self, to: Self, t: f32) -> Self {
+        self.scale(1.0 - t).add(to.scale(t))
+    }
+}
+
+/// Values that are constant across the triangles of an instance.
+#[derive(Clone, Copy, Debug)]
+pub struct Uniforms {
+    /// Transforms from model space to camera space.
+    pub model_view: Mat4,
+    /// Transforms model space normals to camera space.
+    pub normal_matrix: Mat4,
+    /// Transforms from camera space to clip space.
+    pub projection: Mat4,
+    /// Multiplies the alpha of the instance's colors.
+    pub opacity: f32,
+    /// The index of the instance in the scene.
+    pub instance: usize,
+}
+
+/// The attributes of a model vertex as seen by a vertex shader.
+#[derive(Clone, Copy, Debug)]
+pub struct VertexInput {
+    /// The position in model space.
+    pub position: Vec4,
+    /// The normal of the triangle the vertex belongs to, in model space.
+    pub normal: Vec4,
+    /// The color of the vertex in the triangle.
+    pub color: ColorF32,
+    /// The texture coordinates, or zeros for triangles without any.
+    pub uv: [f32; 2],
+    /// The index of the triangle in the model.
+    pub triangle: usize,
+    /// The index of the vertex within the triangle, from 0 to 2.
+    pub corner: usize,
+}
+
+/// A fragment as seen by a fragment shader.
+#[derive(Clone, Copy, Debug)]
+pub struct FragmentInput<V> {
+    /// The reciprocal of the camera space depth.
+    pub depth: f32,
+    /// The varyings interpolated with perspective correction.
+    pub varyings: V,
+}
+
+/// Transforms model vertices into clip space and computes their varyings.
+pub trait VertexShader {
+    /// What the shader outputs for each vertex besides its position.
+    type Varyings: Varyings;
+
+    /// Returns the clip space position and varyings of a vertex.
+    fn shade(&self, uniforms: &Uniforms, vertex: &VertexInput) -> ClipVertex<Self::Varyings>;
+}
+
+/// Computes the color of fragments.
+pub trait FragmentShader<V> {
+    /// Returns the color of a fragment, or `None` to discard it.
+    fn shade(&self, fragment: &FragmentInput<V>) -> Option<ColorF32>;
+}
+
+/// Interpolates the model's vertex colors, faded by the instance's opacity.
+pub struct VertexColorShader;
+
+/// Passes on positions alone, for rendering depths. As a fragment shader, it
+/// shades fragments in grays by the reciprocal of their depth.
+pub struct DepthShader;
+
+/// Shows camera space normals, mapping each axis from [-1, 1] to a color
+/// channel.
+pub struct NormalShader;
+
+/// Shows the barycentric coordinates of fragments as red, green and blue.
+pub struct BarycentricShader;
+
+/// Gives each triangle or each instance its own color.
+pub enum IdShader {
+    /// A color per triangle of a model.
+    Triangle,
+    /// A color per instance of the scene.
+    Instance,
+}
+
+/// Adds a constant amount of light per fragment. Rendered with additive
+/// blending, the result counts the fragments covering each sample, one
+/// `OVERDRAW_STEP` per fragment.
+pub struct OverdrawShader;
+
+/// The brightness each fragment adds in the overdraw view. The heatmap made
+/// from the brightness saturates at the reciprocal number of fragments.
+pub const OVERDRAW_STEP: f32 = 1.0 / 8.0;
+
+impl Uniforms {
+    /// Transforms a model space position to clip space.
+    pub fn clip_position(&self, position: Vec4) -> Vec4 {
+        self.projection * (self.model_view * position)
+    }
+}
+
+impl<const N: usize> Varyings for [f32; N] {
+    fn add(self, rhs: Self) -> Self {
+        let mut v = self;
+        for (v, rhs) in v.iter_mut().zip(rhs) {
+            *v += rhs;
+        }
+        v
+    }
+
+    fn scale(self, s: f32) -> Self {
+        self.map(|v| v * s)
+    }
+}
+
+impl VertexShader for VertexColorShader {
+    type Varyings = [f32; 4];
+
+    fn shade(&self, uniforms: &Uniforms, vertex: &VertexInput) -> ClipVertex<[f32; 4]> {
+        let (r, g, b) = vertex.color.rgb();
+        let a = vertex.color.alpha() * uniforms.opacity;
+        ClipVertex::new(uniforms.clip_position(vertex.position), [r, g, b, a])
+    }
+}
+
+impl FragmentShader<[f32; 4]> for VertexColorShader {
+    fn shade(&self, fragment: &FragmentInput<[f32; 4]>) -> Option<ColorF32> {
+        let [r, g, b, a] = fragment.varyings;
+        Some(ColorF32::new_rgba(r, g, b, a))
+    }
+}
+
+impl VertexShader for DepthShader {
+    type Varyings = [f32; 0];
+
+    fn shade(&self, uniforms: &Uniforms, vertex: &VertexInput) -> ClipVertex<[f32; 0]> {
+        ClipVertex::new(uniforms.clip_position(vertex.position), [])
+    }
+}
+
+impl FragmentShader<[f32; 0]> for DepthShader {
+    fn shade(&self, fragment: &FragmentInput<[f32; 0]>) -> Option<ColorF32> {
+        let d = fragment.depth;
+        Some(ColorF32::new(d, d, d))
+    }
+}
+
+impl VertexShader for NormalShader {
+    type Varyings = [f32; 3];
+
+    fn shade(&self, uniforms: &Uniforms, vertex: &VertexInput) -> ClipVertex<[f32; 3]> {
+        let normal = (uniforms.normal_matrix * vertex.normal).normalize();
+        ClipVertex::new(
+            uniforms.clip_position(vertex.position),
+            [0, 1, 2].map(|i| normal[i]),
+        )
+    }
+}
+
+impl FragmentShader<[f32; 3]> for NormalShader {
+    fn shade(&self, fragment: &FragmentInput<[f32; 3]>) -> Option<ColorF32> {
+        let [x, y, z] = fragment.varyings.map(|n| n * 0.5 + 0.5);
+        Some(ColorF32::new(x, y, z))
+    }
+}
+
+impl VertexShader for BarycentricShader {
+    type Varyings = [f32; 3];
+
+    fn shade(&self, uniforms: &Uniforms, vertex: &VertexInput) -> ClipVertex<[f32; 3]> {
+        let mut weights = [0.0; 3];
+        weights[vertex.corner] = 1.0;
+        ClipVertex::new(uniforms.clip_position(vertex.position), weights)
+    }
+}
+
+impl FragmentShader<[f32; 3]> for BarycentricShader {
+    fn shade(&self, fragment: &FragmentInput<[f32; 3]>) -> Option<ColorF32> {
+        let [r, g, b] = fragment.varyings;
+        Some(ColorF32::new(r, g, b))
+    }
+}
+
+impl VertexShader for IdShader {
+    type Varyings = [f32; 3];
+
+    fn shade(&self, uniforms: &Uniforms, vertex: &VertexInput) -> ClipVertex<[f32; 3]> {
+        // Every vertex of a triangle gets the same color, so interpolation
+        // leaves it unchanged.
+        let id = match self {
+            IdShader::Triangle => (uniforms.instance << 16) ^ vertex.triangle,
+            IdShader::Instance => uniforms.instance,
+        };
+        let (r, g, b) = id_color(id as u32).rgb();
+        ClipVertex::new(uniforms.clip_position(vertex.position), [r, g, b])
+    }
+}
+
+impl FragmentShader<[f32; 3]> for IdShader {
+    fn shade(&self, fragment: &FragmentInput<[f32; 3]>) -> Option<ColorF32> {
+        let [r, g, b] = fragment.varyings;
+        Some(ColorF32::new(r, g, b))
+    }
+}
+
+impl VertexShader for OverdrawShader {
+    type Varyings = [f32; 0];
+
+    fn shade(&self, uniforms: &Uniforms, vertex: &VertexInput) -> ClipVertex<[f32; 0]> {
+        ClipVertex::new(uniforms.clip_position(vertex.position), [])
+    }
+}
+
+impl FragmentShader<[f32; 0]> for OverdrawShader {
+    fn shade(&self, _fragment: &FragmentInput<[f32; 0]>) -> Option<ColorF32> {
+        Some(ColorF32::new(OVERDRAW_STEP, OVERDRAW_STEP, OVERDRAW_STEP))
+    }
+}
+
+/// Picks a bright, well spread color for an ID by hashing it to a hue.
+fn id_color(id: u32) -> ColorF32 {
+    let hash = id.wrapping_add(1).wrapping_mul(0x9e37_79b9);
+    let hue = (hash >> 8) as f32 / (1 << 24) as f32 * 6.0;
+    let f = hue.fract();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, f, 0.0),
+        1 => (1.0 - f, 1.0, 0.0),
+        2 => (0.0, 1.0, f),
+        3 => (0.0, 1.0 - f, 1.0),
+        4 => (f, 0.0, 1.0),
+        _ => (1.0, 0.0, 1.0 - f),
+    };
+    // Alternate the brightness so that neighboring IDs with similar hues
+    // still stand apart.
+    let value = if hash & 0x80 == 0 { 1.0 } else { 0.6 };
+    ColorF32::new(r * value, g * value, b * value)
+}
@@ -0,0 +1,195 @@
+//! Export of frames as SVG drawings, which stay sharp at any size.
+//!
+//! Triangles are written in painter's order, from the farthest to the
+//! nearest, so that nearer shapes cover farther ones without a depth buffer.
+//! The order comes from each triangle's mean depth, so triangles that cut
+//! through each other can be drawn the wrong way round where they overlap.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::gfx::{BlendMode, ColorF32, Framebuffer, RenderResult, CANVAS_HEIGHT, CANVAS_WIDTH};
+use crate::image::to_u8;
+use crate::render::{project_scene, Fragment, RenderOptions};
+use crate::scene::Scene;
+use crate::shader::{FragmentShader, VertexColorShader};
+
+/// The width of lines in canvas pixels.
+const LINE_WIDTH: f32 = 1.0;
+
+/// How far past their edges filled polygons are stroked in their own color,
+/// which closes the hairline gaps anti-aliasing leaves between neighbors.
+const SEAM_WIDTH: f32 = 0.5;
+
+/// How triangles are drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SvgStyle {
+    /// Polygons filled with one color each.
+    Flat,
+    /// The edges of every triangle as lines, including edges behind nearer
+    /// triangles.
+    Wireframe,
+    /// Edges as lines, hidden wherever nearer triangles cover them. Each
+    /// triangle is filled with the background before its edges are drawn.
+    HiddenLine,
+}
+
+/// A projected triangle to be drawn.
+#[derive(Clone, Copy, Debug)]
+pub struct SvgTriangle {
+    /// The corners in canvas space.
+    pub points: [(f32, f32); 3],
+    /// The mean of the reciprocal camera space depths of the corners, so
+    /// larger depths are nearer.
+    pub depth: f32,
+    /// The fill color, which is the mean of the corners' colors.
+    pub color: ColorF32,
+    /// How the fill is blended with what is behind it.
+    pub blend: BlendMode,
+}
+
+/// Writes the triangles of a scene to an SVG file the size of the canvas. Each
+/// triangle is drawn flat in the mean of its vertex colors. Fails if the scene
+/// is not valid.
+pub fn write_scene<P: AsRef<Path>>(
+    path: P,
+    framebuffer: &mut Framebuffer,
+    scene: &Scene,
+    options: &RenderOptions,
+    style: SvgStyle,
+) -> RenderResult {
+    let shader = &VertexColorShader;
+    let triangles = project_scene(framebuffer, scene, options, shader)?;
+    // Plane space is larger than the canvas by the supersampling factor.
+    let scale = 1.0 / options.supersample as f32;
+    let vertex_color = |f: Fragment<[f32; 4]>| {
+        FragmentShader::shade(shader, &f.to_input()).unwrap_or(ColorF32::BLACK)
+    };
+    let triangles: Vec<SvgTriangle> = triangles
+        .iter()
+        .map(|t| {
+            let points = t.fragments.map(|f| {
+                let (x, y) = framebuffer.plane_to_canvas(f.x, f.y);
+                (x * scale, y * scale)
+            });
+            let [c0, c1, c2] = t.fragments.map(vertex_color);
+            let color = (c0 + c1 + c2) * (1.0 / 3.0);
+            SvgTriangle {
+                points,
+                depth: t.mean_depth(),
+                color: match t.blend {
+                    BlendMode::Opaque => color.with_alpha(1.0),
+                    _ => color,
+                },
+                blend: t.blend,
+            }
+        })
+        .collect();
+    write_svg(
+        path,
+        CANVAS_WIDTH,
+        CANVAS_HEIGHT,
+        &triangles,
+        style,
+        ColorF32::BLACK,
+    )?;
+    Ok(())
+}
+
+/// Writes triangles to an SVG file of a given size over a background color.
+pub fn write_svg<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    triangles: &[SvgTriangle],
+    style: SvgStyle,
+    background: ColorF32,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+    )?;
+    writeln!(
+        out,
+        r#"<rect width="{width}" height="{height}" fill="{}"/>"#,
+        hex(background)
+    )?;
+
+    let mut ordered: Vec<&SvgTriangle> = triangles.iter().collect();
+    // Smaller depths are farther away.
+    ordered.sort_by(|t, u| t.depth.total_cmp(&u.depth));
+    writeln!(
+        out,
+        r#"<g stroke-width="{LINE_WIDTH}" stroke-linecap="round" stroke-linejoin="round">"#
+    )?;
+    for t in ordered {
+        match style {
+            SvgStyle::Flat => write_polygon(&mut out, t, t.color, t.blend)?,
+            SvgStyle::Wireframe => write_edges(&mut out, t)?,
+            SvgStyle::HiddenLine => {
+                write_polygon(&mut out, t, background, BlendMode::Opaque)?;
+                write_edges(&mut out, t)?;
+            }
+        }
+    }
+    writeln!(out, "</g>")?;
+    writeln!(out, "</svg>")?;
+    out.flush()
+}
+
+/// Writes a triangle as a filled polygon. Opaque polygons are also stroked in
+/// their fill color to close seams, which blended polygons cannot be without
+/// blending their edges twice.
+fn write_polygon(
+    out: &mut impl Write,
+    t: &SvgTriangle,
+    color: ColorF32,
+    blend: BlendMode,
+) -> io::Result<()> {
+    let points = t.points.map(|(x, y)| format!("{x:.2},{y:.2}")).join(" ");
+    write!(out, r#"<polygon points="{points}" fill="{}""#, hex(color))?;
+    match blend {
+        BlendMode::Opaque => write!(
+            out,
+            r#" stroke="{}" stroke-width="{SEAM_WIDTH}""#,
+            hex(color)
+        )?,
+        BlendMode::Alpha => write!(out, r#" fill-opacity="{:.3}""#, color.alpha())?,
+        BlendMode::Additive => write!(
+            out,
+            r#" fill-opacity="{:.3}" style="mix-blend-mode:plus-lighter""#,
+            color.alpha()
+        )?,
+        BlendMode::Multiply => write!(
+            out,
+            r#" fill-opacity="{:.3}" style="mix-blend-mode:multiply""#,
+            color.alpha()
+        )?,
+    }
+    writeln!(out, "/>")
+}
+
+/// Writes the edges of a triangle as lines in the triangle's color.
+fn write_edges(out: &mut impl Write, t: &SvgTriangle) -> io::Result<()> {
+    let [p0, p1, p2] = t.points;
+    for ((x1, y1), (x2, y2)) in [(p0, p1), (p1, p2), (p2, p0)] {
+        write!(
+            out,
+            r#"<line x1="{x1:.2}" y1="{y1:.2}" x2="{x2:.2}" y2="{y2:.2}" stroke="{}""#,
+            hex(t.color)
+        )?;
+        if t.color.alpha() < 1.0 {
+            write!(out, r#" stroke-opacity="{:.3}""#, t.color.alpha())?;
+        }
+        writeln!(out, "/>")?;
+    }
+    Ok(())
+}
+
+/// Formats a color as a hexadecimal RGB triplet, dropping its alpha.
+fn hex(c: ColorF32) -> String {
+    let (r, g, b) = c.rgb();
+    format!("#{:02x}{:02x}{:02x}", to_u8(r), to_u8(g), to_u8(b))
+}
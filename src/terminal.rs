@@ -0,0 +1,300 @@
+//! Presentation of frames in a terminal, for working without a display.
+//!
+//! Frames are drawn with ANSI escape sequences, either as truecolor half
+//! block characters giving two pixels per character cell, or as characters
+//! picked by brightness from a ramp. The terminal is switched to raw mode so
+//! that single key presses can be read without waiting for a line.
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::gfx::ColorF32;
+use crate::image::{to_u8, Image};
+
+/// Characters from darkest to brightest for drawing frames as text.
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+
+/// The size used when the terminal's size cannot be found.
+const DEFAULT_SIZE: (u32, u32) = (80, 24);
+
+/// The byte starting escape sequences, which is also sent alone for the
+/// escape key.
+const ESCAPE: u8 = 0x1b;
+
+/// How frames are drawn in the terminal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TerminalMode {
+    /// Upper half blocks with the top pixel as the foreground color and the
+    /// bottom pixel as the background color.
+    HalfBlocks,
+    /// Uncolored characters from a ramp of brightness.
+    Ascii,
+}
+
+/// A terminal in raw mode showing an alternate screen, which is restored when
+/// dropped.
+pub struct Terminal {
+    mode: TerminalMode,
+    /// The terminal settings to restore, as printed by `stty -g`.
+    saved: String,
+    /// The size in character cells as columns and rows.
+    size: (u32, u32),
+    keys: Receiver<u8>,
+    /// The text of the frame being drawn, kept between frames so that it is
+    /// not reallocated.
+    frame: String,
+}
+
+/// Where a `KeyReader` is within the bytes a terminal sends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum KeyState {
+    Ground,
+    /// After an escape byte.
+    Escape,
+    /// Within a control sequence, as sent for arrow and function keys, which
+    /// ends with a byte from `@` to `~`.
+    ControlSequence,
+    /// After `ESC O`, which some terminals send before the single byte of an
+    /// arrow key.
+    SingleShift,
+}
+
+/// Separates the keys a terminal sends from the escape sequences of keys
+/// without a byte of their own, such as the arrow keys, so that the letters
+/// ending those sequences are not mistaken for key presses.
+struct KeyReader {
+    state: KeyState,
+}
+
+impl Terminal {
+    /// Switches the terminal to raw mode and an alternate screen, and starts
+    /// reading key presses.
+    pub fn new(mode: TerminalMode) -> io::Result<Self> {
+        let saved = stty(&["-g"])?.trim().to_string();
+        stty(&["raw", "-echo"])?;
+        let size = stty_size().unwrap_or(DEFAULT_SIZE);
+
+        // Reading blocks, so it happens on its own thread, which ends along
+        // with the program.
+        let (sender, keys) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 64];
+            let mut reader = KeyReader::new();
+            while let Ok(n @ 1..) = io::stdin().read(&mut buffer) {
+                for key in reader.read(&buffer[..n]) {
+                    if sender.send(key).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        // Use the alternate screen and hide the cursor.
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(Terminal {
+            mode,
+            saved,
+            size,
+            keys,
+            frame: String::new(),
+        })
+    }
+
+    /// Returns the next key pressed since the last call, if any, without
+    /// waiting. Keys are returned as the bytes the terminal sends for them,
+    /// and keys sent as escape sequences are left out.
+    pub fn poll_key(&self) -> Option<u8> {
+        self.keys.try_recv().ok()
+    }
+
+    /// The size of the terminal in character cells as columns and rows, as
+    /// it was when the terminal was switched to raw mode.
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Draws an image scaled to fit the terminal above a line of status text.
+    /// Character cells are taken to be twice as tall as they are wide.
+    pub fn draw(&mut self, image: &Image, status: &str) -> io::Result<()> {
+        let (columns, rows) = self.size();
+        let rows = rows.saturating_sub(1).max(1);
+        let aspect = image.height() as f32 / image.width() as f32;
+        // The number of pixels each character cell shows vertically.
+        let cell_pixels = match self.mode {
+            TerminalMode::HalfBlocks => 2,
+            TerminalMode::Ascii => 1,
+        };
+        // Fit the image into the terminal with square pixels, where a cell
+        // is one pixel wide and two pixels tall.
+        let width = ((columns as f32).min(2.0 * rows as f32 / aspect) as u32).max(1);
+        let height = ((width as f32 * aspect / 2.0).round() as u32).clamp(1, rows);
+        let pixels = resample(image, width, height * cell_pixels);
+
+        let frame = &mut self.frame;
+        frame.clear();
+        // Start from the top left corner.
+        frame.push_str("\x1b[H");
+        for y in 0..height as usize {
+            let row = |i: usize| &pixels[i * width as usize..(i + 1) * width as usize];
+            match self.mode {
+                TerminalMode::HalfBlocks => {
+                    let (top, bottom) = (row(2 * y), row(2 * y + 1));
+                    for (t, b) in top.iter().zip(bottom) {
+                        let (tr, tg, tb) = rgb_u8(*t);
+                        let (br, bg, bb) = rgb_u8(*b);
+                        // Writing to a string cannot fail.
+                        let _ = write!(
+                            frame,
+                            "\x1b[38;2;{tr};{tg};{tb}m\x1b[48;2;{br};{bg};{bb}m\u{2580}"
+                        );
+                    }
+                    frame.push_str("\x1b[0m");
+                }
+                TerminalMode::Ascii => {
+                    for c in row(y) {
+                        frame.push(ramp_char(*c));
+                    }
+                }
+            }
+            // Clear whatever is left of the line from a wider frame.
+            frame.push_str("\x1b[K\r\n");
+        }
+        // Clear the lines below the image, then write the status on the
+        // last line.
+        let _ = write!(frame, "\x1b[J\x1b[{};1H{status}", rows + 1);
+
+        let mut out = io::stdout().lock();
+        out.write_all(frame.as_bytes())?;
+        out.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+impl KeyReader {
+    fn new() -> Self {
+        KeyReader {
+            state: KeyState::Ground,
+        }
+    }
+
+    /// Returns the keys among bytes read from the terminal. Escape sequences
+    /// may be split between reads, but an escape byte ending a read is taken
+    /// to be the escape key.
+    fn read(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut keys = Vec::new();
+        for &byte in bytes {
+            self.state = match (self.state, byte) {
+                (KeyState::Ground, ESCAPE) => KeyState::Escape,
+                (KeyState::Ground, _) => {
+                    keys.push(byte);
+                    KeyState::Ground
+                }
+                (KeyState::Escape, b'[') => KeyState::ControlSequence,
+                (KeyState::Escape, b'O') => KeyState::SingleShift,
+                // Alt with a key, which is dropped like the other sequences.
+                (KeyState::Escape, _) => KeyState::Ground,
+                (KeyState::ControlSequence, b'@'..=b'~') => KeyState::Ground,
+                (KeyState::ControlSequence, _) => KeyState::ControlSequence,
+                (KeyState::SingleShift, _) => KeyState::Ground,
+            };
+        }
+        if self.state == KeyState::Escape {
+            keys.push(ESCAPE);
+            self.state = KeyState::Ground;
+        }
+        keys
+    }
+}
+
+/// Finds the size of the terminal in character cells as columns and rows.
+fn stty_size() -> Option<(u32, u32)> {
+    let size = stty(&["size"]).ok()?;
+    let mut words = size.split_whitespace().map(str::parse::<u32>);
+    let rows = words.next()?.ok()?;
+    let columns = words.next()?.ok()?;
+    Some((columns, rows))
+}
+
+/// Runs `stty` on the terminal with some arguments and returns what it
+/// printed.
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(io::Error::other(format!("stty failed: {message}")));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Scales an image to a given size, averaging the pixels that fall within
+/// each new pixel.
+fn resample(image: &Image, width: u32, height: u32) -> Vec<ColorF32> {
+    let (source_width, source_height) = (image.width(), image.height());
+    let source = image.pixels();
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        let y0 = y * source_height / height;
+        let y1 = ((y + 1) * source_height / height).max(y0 + 1);
+        for x in 0..width {
+            let x0 = x * source_width / width;
+            let x1 = ((x + 1) * source_width / width).max(x0 + 1);
+            let mut sum = ColorF32::new(0.0, 0.0, 0.0);
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    sum = sum + source[(sy * source_width + sx) as usize];
+                }
+            }
+            let (r, g, b) = (sum * (1.0 / ((x1 - x0) * (y1 - y0)) as f32)).rgb();
+            pixels.push(ColorF32::new(r, g, b));
+        }
+    }
+    pixels
+}
+
+fn rgb_u8(c: ColorF32) -> (u8, u8, u8) {
+    let (r, g, b) = c.rgb();
+    (to_u8(r), to_u8(g), to_u8(b))
+}
+
+/// Picks the character of the ramp matching a color's brightness.
+fn ramp_char(c: ColorF32) -> char {
+    let (r, g, b) = c.rgb();
+    let luminance = (0.2126 * r + 0.7152 * g + 0.0722 * b).clamp(0.0, 1.0);
+    let i = (luminance * (ASCII_RAMP.len() - 1) as f32).round() as usize;
+    ASCII_RAMP[i] as char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_escape_sequences() {
+        let mut reader = KeyReader::new();
+        // Right and left arrows, in both of the forms terminals send.
+        assert_eq!(reader.read(b"\x1b[C\x1b[Dp\x1bOC\x1bODw"), b"pw");
+        // Sequences with parameters, such as Ctrl-Right, and ones split
+        // between reads.
+        assert_eq!(reader.read(b"\x1b[1;5Cd\x1b["), b"d");
+        assert_eq!(reader.read(b"15~c"), b"c");
+        // Alt with a key.
+        assert_eq!(reader.read(b"\x1bqq"), b"q");
+        // The escape key alone.
+        assert_eq!(reader.read(b"\x1b"), [ESCAPE]);
+        assert_eq!(reader.read(b"c"), b"c");
+    }
+}
@@ -0,0 +1,58 @@
+//! Helpers shared by the tests of several modules.
+
+use crate::math::vec::Vec4;
+use crate::ray::{intersect_triangle, Ray};
+
+/// A linear congruential generator, so that tests need no dependencies and
+/// always see the same numbers.
+pub struct Random(u32);
+
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        Random(seed)
+    }
+
+    fn advance(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        self.0
+    }
+
+    /// A number between -1 and 1.
+    pub fn next(&mut self) -> f32 {
+        (self.advance() >> 8) as f32 / (1 << 23) as f32 - 1.0
+    }
+
+    /// A number between 0 and 1.
+    pub fn fraction(&mut self) -> f32 {
+        (self.advance() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    pub fn byte(&mut self) -> u8 {
+        (self.advance() >> 24) as u8
+    }
+
+    /// A point with coordinates between `-scale` and `scale`.
+    pub fn point(&mut self, scale: f32) -> Vec4 {
+        self.vector(scale) + Vec4::new(0.0, 0.0, 0.0, 1.0)
+    }
+
+    /// A direction with coordinates between `-scale` and `scale`.
+    pub fn vector(&mut self, scale: f32) -> Vec4 {
+        Vec4::new(
+            scale * self.next(),
+            scale * self.next(),
+            scale * self.next(),
+            0.0,
+        )
+    }
+}
+
+/// Finds the nearest of some triangles a ray hits, and the ray parameter of
+/// the hit, by testing every one of them.
+pub fn brute_force_raycast(triangles: &[[Vec4; 3]], ray: &Ray) -> Option<(usize, f32)> {
+    triangles
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &t)| Some((i, intersect_triangle(ray, t)?.0)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}